@@ -7,7 +7,7 @@ import { type DriftResponse, processResponse } from "@/types/response";
 import StatCard from "@/components/app_ui/DashboardCard";
 import { open } from "@tauri-apps/plugin-shell";
 import RecentPosts from "@/components/app_ui/post_grid";
-import type { Post } from "@/types/post";
+import type { Post, SiteStats } from "@/types/post";
 
 export default function dashboard() {
 	const { setSelectedPage } = useSelectedPage();
@@ -15,6 +15,7 @@ export default function dashboard() {
   const [postCount, setPostCount] = useState(0);
   const [deployUrl, setDeployUrl] = useState("");
   const [recentPosts, setRecentPosts] = useState<Post[]>([]);
+  const [siteStats, setSiteStats] = useState<SiteStats | null>(null);
   const [isLoaded, setIsLoaded] = useState(false);
 
   const handleVisit = async () => {
@@ -64,6 +65,16 @@ export default function dashboard() {
 
     loadRecentPosts();
 
+    const loadSiteStats = async () => {
+      if (!selectedSite) return;
+      const response = await invoke<DriftResponse<SiteStats>>("get_site_stats", { siteId: selectedSite.id });
+      if (processResponse(response)) {
+        setSiteStats(response.body);
+      }
+    }
+
+    loadSiteStats();
+
     if (selectedSite) {
       setDeployUrl(`https://api.netlify.com/api/v1/badges/${selectedSite.id}/deploy-status`);
     }
@@ -84,6 +95,13 @@ export default function dashboard() {
         <p>Loading...</p>
       }
 
+      {siteStats && siteStats.post_count > 0 && (
+        <p className="mt-4 text-sm text-muted-foreground">
+          {siteStats.word_count} words in total, {siteStats.average_word_count} per post on average
+          (longest {siteStats.longest_word_count}), about {siteStats.reading_time} min of reading.
+        </p>
+      )}
+
       <div className="my-8">
         <h1 className="text-lg">Recent Posts</h1>
         <hr className="mb-2" />
@@ -24,6 +24,7 @@ import {
 } from "@/components/ui/form";
 import { invoke } from "@tauri-apps/api/core";
 import { type DriftResponse, processResponse } from "@/types/response";
+import openFilePicker from "@/lib/file_picker";
 
 // Add schema at top of file
 const postFormSchema = z.object({
@@ -34,6 +35,8 @@ const postFormSchema = z.object({
   tags: z.array(z.string()),
 });
 
+const isAssetUrl = (url: string) => url.startsWith('/assets/');
+
 type PostFormValues = z.infer<typeof postFormSchema>;
 
 export default function EditPost() {
@@ -83,13 +86,32 @@ export default function EditPost() {
     };
 
     console.log(watchImage);
-    if (watchImage) {
+    // imported images live in the site's assets, the webview can't fetch them
+    if (isAssetUrl(watchImage)) {
+      setIsValidUrl(true);
+    } else if (watchImage) {
       checkUrl(watchImage);
     } else {
       setIsValidUrl(false);
     }
   }, [watchImage]);
 
+  // copies the picked file into the site's assets and uses its URL
+  const handleChooseImage = async () => {
+    const filePath = await openFilePicker();
+    if (!filePath || Array.isArray(filePath)) return;
+
+    const response = await invoke<DriftResponse<{ image: string }>>("import_post_image", {
+      siteId: selectedSite?.id,
+      filePath,
+    });
+
+    if (processResponse(response)) {
+      form.setValue('image', response.body.image);
+      setImage(response.body.image);
+    } else alert(response.message);
+  };
+
   const handleAddTag = (e: React.KeyboardEvent<HTMLInputElement>) => {
     if (e.key === 'Enter' && currentTag.trim()) {
       e.preventDefault(); // Prevent form submission
@@ -197,17 +219,23 @@ export default function EditPost() {
                 name="image"
                 render={({ field }) => (
                   <FormItem>
-                    <FormLabel>Featured Image URL</FormLabel>
-                    <FormControl>
-                      <Input
-                        {...field}
-                        onChange={(e) => {
-                          field.onChange(e);
-                          setImage(e.target.value);
-                        }}
-                        value={field.value}
-                      />
-                    </FormControl>
+                    <FormLabel>Featured Image</FormLabel>
+                    <div className="flex gap-2">
+                      <FormControl>
+                        <Input
+                          {...field}
+                          placeholder="https://... or choose a file"
+                          onChange={(e) => {
+                            field.onChange(e);
+                            setImage(e.target.value);
+                          }}
+                          value={field.value}
+                        />
+                      </FormControl>
+                      <Button type="button" variant="outline" onClick={handleChooseImage}>
+                        Choose File
+                      </Button>
+                    </div>
                     <FormMessage />
                   </FormItem>
                 )}
@@ -265,7 +293,9 @@ export default function EditPost() {
         <CardContent className="pt-6">
           <div className="space-y-4">
             <div className="aspect-video rounded-lg bg-muted flex items-center justify-center">
-              {image ? (
+              {isAssetUrl(image) ? (
+                <p className="text-muted-foreground">Stored in the site's assets: {image.replace('/assets/', '')}</p>
+              ) : image ? (
                 <img
                   src={image}
                   alt={title}
@@ -286,7 +316,7 @@ export default function EditPost() {
                 ))}
               </div>
               <p className="text-muted-foreground">
-                {excerpt || 'No excerpt provided'}
+                {excerpt || 'Excerpt will be made from the first paragraph'}
               </p>
             </div>
 
@@ -12,6 +12,8 @@ import { Label } from "@/components/ui/label";
 import { zodResolver } from "@hookform/resolvers/zod";
 import {
 	ChevronLeft,
+	Eye,
+	EyeOff,
 	SquareArrowOutUpRight,
 	PencilLine,
 	Rocket,
@@ -61,6 +63,8 @@ export default function EditSite({
 	const { toast } = useToast();
 	const [isDeleteConfirmOpen, setisDeleteConfirmOpen] = useState(false);
 	const [isNavigateOpen, setisNavigateOpen] = useState(false);
+	// URL of the running preview, null when there isn't one
+	const [preview_url, set_preview_url] = useState<string | null>(null);
 	const [site_details, set_site_details] = useState<WebsiteDetails>({
 		name: "",
 		domain: "",
@@ -124,14 +128,51 @@ export default function EditSite({
 
 	const deploy_site = async () => {
 
-		const response = await invoke<DriftResponse>("deploy_site", {
+		const response = await invoke<DriftResponse<{ warnings?: string[] }>>("deploy_site", {
 			siteId: site_details.id,
 		});
 
 		const result = processResponse(response);
+		// e.g. images in posts that couldn't be found
+		const warnings = result ? response.body?.warnings ?? [] : [];
 
 		toast({
 			title: "Deploy status",
+			description: warnings.length
+				? `${response.message}\n${warnings.join("\n")}`
+				: response.message
+		});
+	};
+
+	const preview_site = async () => {
+
+		const response = await invoke<DriftResponse<{ url: string }>>("start_preview", {
+			siteId: site_details.id,
+		});
+
+		const result = processResponse(response);
+		if (result) {
+			set_preview_url(response.body.url);
+		}
+
+		toast({
+			title: "Preview status",
+			description: response.message
+		});
+	};
+
+	const stop_preview = async () => {
+
+		const response = await invoke<DriftResponse>("stop_preview", {
+			siteId: site_details.id,
+		});
+
+		processResponse(response);
+		// either way there's no preview running anymore
+		set_preview_url(null);
+
+		toast({
+			title: "Preview status",
 			description: response.message
 		});
 	};
@@ -215,6 +256,17 @@ export default function EditSite({
 					<Rocket />
 					&nbsp;Deploy site
 				</Button>
+				{preview_url ? (
+					<Button onClick={stop_preview} className="w-52">
+						<EyeOff />
+						&nbsp;Stop preview
+					</Button>
+				) : (
+					<Button onClick={preview_site} className="w-52">
+						<Eye />
+						&nbsp;Preview site
+					</Button>
+				)}
 			</div>
 			<div className="flex flex-row gap-8">
 				<Button
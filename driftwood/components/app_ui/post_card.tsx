@@ -31,6 +31,11 @@ const Card: React.FC<PostsListProps> = ({ posts, onEditClick }) => {
 									<p className="card__excerpt">{post.excerpt}</p>
 								)}
 								<p className="card__date">{post.date}</p>
+								{post.stats && (
+									<p className="card__stats">
+										{post.stats.word_count} words · {post.stats.reading_time} min read
+									</p>
+								)}
                 <p>
 								{post.tags.map((tag, index) => {
 									const isLast = index === post.tags.length - 1;
@@ -2,13 +2,14 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { Button } from "@/components/ui/button";
+import { Input } from "@/components/ui/input";
 import PostCard from "@/components/app_ui/post_card";
 import {
 	type DriftResponse,
 	processResponse
 } from "@/types/response";
 
-import type { Post } from "@/types/post";
+import type { Post, SearchResult } from "@/types/post";
 
 export default function Posts({
 	site_id,
@@ -16,6 +17,25 @@ export default function Posts({
 	onReturnClick,
 }: { site_id: string; onEditClick: (post_id: number) => void; onReturnClick: (site_id: string) => void; }) {
 	const [data, setData] = useState<Post[] | null>(null);
+	const [query, setQuery] = useState("");
+	const [results, setResults] = useState<SearchResult[] | null>(null);
+
+	useEffect(() => {
+		if (query.trim() === "") {
+			setResults(null);
+			return;
+		}
+		// wait for a pause in typing before searching
+		const timer = setTimeout(async () => {
+			const response = await invoke<DriftResponse<SearchResult[]>>("search_posts", {
+				siteId: site_id,
+				query,
+			});
+			if (processResponse(response))
+				setResults(response.body);
+		}, 200);
+		return () => clearTimeout(timer);
+	}, [query, site_id]);
 
 	useEffect(() => {
 		const listPosts = async () => {
@@ -38,8 +58,33 @@ export default function Posts({
 		<div className="w-full">
 			<div className="flex gap-8">
 				<h1 className="text-4xl pb-2">Posts</h1>
+				<Input
+					className="max-w-sm"
+					placeholder="Search posts"
+					value={query}
+					onChange={(e) => setQuery(e.target.value)}
+				/>
 			</div>
-			{data && <PostCard posts={data} onEditClick={onEditClick} />}
+			{results ? (
+				<ul className="my-4 space-y-4">
+					{results.length === 0 && <li>No posts match "{query}"</li>}
+					{results.map((result) => (
+						<li key={result.post_id}>
+							<button type="button" className="text-left" onClick={() => onEditClick(result.post_id)}>
+								<h2 className="text-lg font-bold">{result.title}</h2>
+								{/* escaped by the backend, only the <mark> tags are HTML */}
+								<p
+									className="text-sm text-muted-foreground"
+									// biome-ignore lint/security/noDangerouslySetInnerHtml: <explanation>
+									dangerouslySetInnerHTML={{ __html: result.snippet }}
+								/>
+							</button>
+						</li>
+					))}
+				</ul>
+			) : (
+				data && <PostCard posts={data} onEditClick={onEditClick} />
+			)}
 			<div className="flex gap-8">
 				<Button onClick={() => onReturnClick(site_id)}>
 						Return to site
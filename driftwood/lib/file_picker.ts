@@ -10,7 +10,7 @@ export default async function openFilePicker() {
 		filters: [
 			{
 				name: "Images",
-				extensions: ["ico", "jpg", "jpeg", "png"],
+				extensions: ["ico", "jpg", "jpeg", "png", "gif", "webp"],
 			},
 		],
 	});
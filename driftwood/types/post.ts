@@ -2,6 +2,8 @@ export type Post = {
 	post_id: number;
 	site_id: string;
 	title: string;
+	// set by the backend, left out when saving so it follows title changes
+	slug?: string;
 	tags: string[];
 	date: string;
 	image: string;
@@ -9,4 +11,36 @@ export type Post = {
 	excerpt: string;
 	content: string;
 	published: boolean;
-};
\ No newline at end of file
+	// counted by the backend whenever the post is saved
+	stats?: PostStats;
+};
+
+export type PostStats = {
+	word_count: number;
+	reading_time: number;
+	heading_count: number;
+	image_count: number;
+	link_count: number;
+};
+
+export type SiteStats = {
+	post_count: number;
+	word_count: number;
+	average_word_count: number;
+	longest_word_count: number;
+	reading_time: number;
+	heading_count: number;
+	image_count: number;
+	link_count: number;
+};
+
+// a post matching search_posts, snippet is escaped HTML with matches in <mark>
+export type SearchResult = {
+	post_id: number;
+	site_id: string;
+	site_name: string | null;
+	title: string;
+	slug: string;
+	snippet: string;
+	rank: number;
+};
@@ -0,0 +1,325 @@
+/// Per-site store for images and other binary files used by posts
+use crate::driftwood::SiteDetails;
+use anyhow::{anyhow, Context, Result};
+use pulldown_cmark::{Event, Parser, Tag};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// Extensions of the files a post can use as images
+pub const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "webp", "svg", "avif"];
+
+/// AssetStore struct
+/// Files are named by the SHA1 of their contents, so storing the same image twice is a no-op
+pub struct AssetStore {
+    pub dir: PathBuf,
+}
+
+impl AssetStore {
+    /// Opens the asset store for a site, creating `sites/{id}/assets` if needed
+    pub fn for_site(site: &SiteDetails) -> Result<AssetStore> {
+        AssetStore::open(site.build_site_path()?.join("assets"))
+    }
+
+    /// Opens the asset store for a site by its ID
+    pub fn for_site_id(site_id: &str) -> Result<AssetStore> {
+        AssetStore::open(SiteDetails::site_path_for(site_id).join("assets"))
+    }
+
+    fn open(dir: PathBuf) -> Result<AssetStore> {
+        if !dir.exists() {
+            fs::create_dir_all(&dir).context("Failed to create this site's 'assets' directory")?;
+        }
+        Ok(AssetStore { dir })
+    }
+
+    /// Stores the bytes under their content hash
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The file contents
+    /// * `extension` - The file extension to keep, without the dot
+    ///
+    /// # Returns
+    ///
+    /// The stored file name, e.g. `3f786850e387550fdab836ed7e6dc881de23001b.png`
+    pub fn store_bytes(&self, bytes: &[u8], extension: &str) -> Result<String> {
+        let hash = sha1_smol::Sha1::from(bytes).digest().to_string();
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        let file_name = if extension.is_empty() {
+            hash
+        } else {
+            format!("{}.{}", hash, extension)
+        };
+
+        let path = self.dir.join(&file_name);
+        if !path.exists() {
+            fs::write(&path, bytes).context("Failed to write asset to disk")?;
+        }
+        Ok(file_name)
+    }
+
+    /// Copies a local file into the store, keeping its extension
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to import
+    ///
+    /// # Returns
+    ///
+    /// The stored file name
+    pub fn store_file(&self, path: &Path) -> Result<String> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_string())
+            .or_else(|| image_extension_for(&bytes).map(String::from))
+            .unwrap_or_default();
+        self.store_bytes(&bytes, &extension)
+    }
+
+    /// Imports an image the user picked, URLs and already imported assets are kept as is
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - A local file path, or a URL the post already uses
+    ///
+    /// # Returns
+    ///
+    /// The URL to save on the post, `/assets/{hash}.{ext}` for imported files.
+    /// An error when the image is a path that doesn't lead to a file, e.g. one that was moved
+    pub fn import_image(&self, image: &str) -> Result<String> {
+        if image.starts_with("/assets/") || image.starts_with("//") || has_url_scheme(image) {
+            return Ok(image.to_string());
+        }
+        let path = Path::new(image);
+        if !path.is_file() {
+            return Err(anyhow!("No image found at {}", image));
+        }
+        let file_name = self.store_file(path)?;
+        Ok(AssetStore::url_for(&file_name))
+    }
+
+    /// The URL a stored asset is served from on the deployed site
+    pub fn url_for(file_name: &str) -> String {
+        format!("/assets/{}", file_name)
+    }
+}
+
+/// LocalImages struct
+/// Images in a post's Markdown that pointed at files on this computer
+/// urls: Each image destination as written in the Markdown, mapped to the stored asset's URL
+/// missing: Local paths that didn't lead to an image, or led outside the site, reported as build warnings
+#[derive(Debug, Default)]
+pub struct LocalImages {
+    pub urls: HashMap<String, String>,
+    pub missing: Vec<String>,
+}
+
+/// Where a Markdown image destination points
+enum ImageRef {
+    Local(PathBuf),
+    Missing,
+    Remote,
+}
+
+/// Copies the local images a post's Markdown references into the site's asset store
+///
+/// # Arguments
+///
+/// * `markdown` - The post's content
+/// * `site_dir` - The site's directory, relative paths are looked up in its `md_posts` and then in it,
+///   and must stay inside it
+///
+/// # Returns
+///
+/// The asset URLs to render the images with, and the paths that couldn't be found
+pub fn import_local_images(markdown: &str, site_dir: &Path) -> Result<LocalImages> {
+    let mut images = LocalImages::default();
+    // only opened once there's something to store, so sites without local images don't get an empty directory
+    let mut store: Option<AssetStore> = None;
+
+    for event in Parser::new(markdown) {
+        let Event::Start(Tag::Image { dest_url, .. }) = event else {
+            continue;
+        };
+        let dest = dest_url.to_string();
+        if images.urls.contains_key(&dest) || images.missing.contains(&dest) {
+            continue;
+        }
+
+        match resolve_image(&dest, site_dir) {
+            ImageRef::Local(path) => {
+                let store = match store.as_ref() {
+                    Some(store) => store,
+                    None => store.insert(AssetStore::open(site_dir.join("assets"))?),
+                };
+                let file_name = store.store_file(&path)?;
+                images.urls.insert(dest, AssetStore::url_for(&file_name));
+            }
+            ImageRef::Missing => images.missing.push(dest),
+            ImageRef::Remote => {}
+        }
+    }
+
+    Ok(images)
+}
+
+fn resolve_image(dest: &str, site_dir: &Path) -> ImageRef {
+    let dest = dest.trim();
+    if dest.is_empty()
+        || dest.starts_with('#')
+        || dest.starts_with("//")
+        || dest.starts_with("/assets/")
+    {
+        return ImageRef::Remote;
+    }
+
+    let (path, explicit) = match dest.strip_prefix("file://") {
+        Some(path) => (path, true),
+        None if has_url_scheme(dest) => return ImageRef::Remote,
+        None => (dest, false),
+    };
+    let path = urlencoding::decode(path)
+        .map(|path| path.to_string())
+        .unwrap_or_else(|_| path.to_string());
+    let path = PathBuf::from(path);
+
+    if path.is_absolute() {
+        return match path.is_file() {
+            true if is_image_file(&path) => ImageRef::Local(path),
+            true => ImageRef::Missing,
+            // a site-absolute URL like /images/logo.png, unless it was a file:// link
+            false if explicit => ImageRef::Missing,
+            false => ImageRef::Remote,
+        };
+    }
+
+    // ../ or a symlink could otherwise copy any file on the computer into the deployed site
+    let Ok(site_dir) = site_dir.canonicalize() else {
+        return ImageRef::Missing;
+    };
+    [site_dir.join("md_posts"), site_dir.clone()]
+        .into_iter()
+        .filter_map(|dir| dir.join(&path).canonicalize().ok())
+        .find(|candidate| candidate.starts_with(&site_dir) && candidate.is_file())
+        .filter(|candidate| is_image_file(candidate))
+        .map_or(ImageRef::Missing, ImageRef::Local)
+}
+
+/// Whether a file has an image extension, or starts like an image when it has none
+fn is_image_file(path: &Path) -> bool {
+    match path.extension() {
+        Some(extension) => {
+            IMAGE_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str())
+        }
+        None => {
+            let mut header = Vec::with_capacity(12);
+            File::open(path)
+                .and_then(|file| file.take(12).read_to_end(&mut header))
+                .is_ok()
+                && image_extension_for(&header).is_some()
+        }
+    }
+}
+
+/// Whether a destination starts with a scheme like https: or data:, single letters are Windows drives
+fn has_url_scheme(dest: &str) -> bool {
+    match dest.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.len() > 1
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+/// Guesses an image's extension from its first bytes, for files saved without one
+pub fn image_extension_for(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_image() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = AssetStore::open(dir.path().join("assets"))?;
+
+        let source = dir.path().join("header");
+        fs::write(&source, b"\x89PNG\r\n\x1a\nnot really a png")?;
+        let url = store.import_image(&source.to_string_lossy())?;
+        assert!(url.starts_with("/assets/") && url.ends_with(".png"));
+        assert!(store.dir.join(url.trim_start_matches("/assets/")).exists());
+
+        // the same file imported again maps to the same asset
+        assert_eq!(store.import_image(&source.to_string_lossy())?, url);
+
+        // URLs and already imported assets are left alone
+        assert_eq!(store.import_image(&url)?, url);
+        assert_eq!(
+            store.import_image("https://example.com/a.jpg")?,
+            "https://example.com/a.jpg"
+        );
+        // a path to nothing would be saved as a broken image
+        assert!(store
+            .import_image(&dir.path().join("moved.png").to_string_lossy())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_local_images() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let site_dir = dir.path().join("site");
+        fs::create_dir_all(site_dir.join("md_posts"))?;
+        fs::write(site_dir.join("md_posts").join("photo.png"), b"relative")?;
+        let absolute = dir.path().join("My Photo.jpg");
+        fs::write(&absolute, b"absolute")?;
+        fs::write(site_dir.join("md_posts").join("notes.txt"), b"not an image")?;
+        fs::write(dir.path().join("outside.png"), b"outside the site")?;
+
+        let markdown = format!(
+            "![a](./photo.png) ![b](<{}>) ![c](missing.png) ![d](https://example.com/x.png) ![e](/images/logo.png) ![f](notes.txt) ![g](../../outside.png)",
+            absolute.display()
+        );
+        let images = import_local_images(&markdown, &site_dir)?;
+
+        assert_eq!(images.urls.len(), 2);
+        assert!(images.urls["./photo.png"].starts_with("/assets/"));
+        assert!(images.urls[&absolute.display().to_string()].ends_with(".jpg"));
+        // only images, and only from inside the site
+        assert_eq!(
+            images.missing,
+            vec!["missing.png", "notes.txt", "../../outside.png"]
+        );
+        assert_eq!(fs::read_dir(site_dir.join("assets"))?.count(), 2);
+
+        // nothing local, no asset directory
+        let other = dir.path().join("other");
+        assert!(
+            import_local_images("![x](https://example.com/x.png)", &other)?
+                .urls
+                .is_empty()
+        );
+        assert!(!other.join("assets").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,852 @@
+/// Renders a site's posts into a directory of static files, ready to be deployed
+use crate::assets::import_local_images;
+use crate::driftwood::{
+    md_to_html, parse_post_date, slugify, HtmlPolicy, Post, SiteDetails, SiteSettings, TocEntry,
+};
+use crate::favicons::{write_favicons, FaviconLink};
+use crate::headers::{effective_headers, headers_file, HeaderRepository, HeaderRule};
+use crate::highlight::{theme_css, HIGHLIGHT_STYLESHEET};
+use crate::images::{responsive_html, ImagePipeline, ImageSet};
+use crate::posts::{PostRepository, FALLBACK_SLUG};
+use crate::redirects::{redirects_file, RedirectRepository, RedirectRule};
+use crate::search_index::{plain_text, SearchDocument, SearchIndex, SEARCH_INDEX_FILE};
+use crate::shortcodes::Shortcodes;
+use crate::sites::SiteRepository;
+use crate::stats::PostStats;
+use crate::themes::{Theme, INDEX_TEMPLATE, POST_TEMPLATE, TAG_TEMPLATE};
+use anyhow::{anyhow, Context, Result};
+use minijinja::{context, Environment};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Posts on each page of the index when a site hasn't changed it
+pub const DEFAULT_POSTS_PER_PAGE: usize = 10;
+
+/// BuildOutput struct
+/// A rendered site on disk
+/// root: The directory the site was rendered into
+/// files: Every file in the build, relative to root and starting with a slash, e.g. /posts/hello.html
+/// warnings: Problems that didn't stop the build, like images that couldn't be found
+#[derive(Debug, Clone)]
+pub struct BuildOutput {
+    pub root: PathBuf,
+    pub files: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl BuildOutput {
+    /// Full path on disk of a file from `files`
+    pub fn path_of(&self, file: &str) -> PathBuf {
+        self.root.join(file.trim_start_matches('/'))
+    }
+}
+
+/// SiteSource struct
+/// Everything from the DB that goes into a build
+/// redirects: Custom rules first, then the ones generated from slug history
+/// headers: The site's custom header rules, the defaults are added when building
+#[derive(Debug, Clone, Default)]
+pub struct SiteSource {
+    pub settings: SiteSettings,
+    pub posts: Vec<Post>,
+    pub redirects: Vec<RedirectRule>,
+    pub headers: Vec<HeaderRule>,
+}
+
+impl SiteSource {
+    /// Reads a site's settings, posts and redirects from the DB
+    pub fn load(site_id: &str) -> Result<SiteSource> {
+        let settings = SiteRepository::new()?
+            .read_settings(site_id)
+            .context("Failed to read the site's settings")?;
+        let posts = PostRepository::new()?
+            .list_all(site_id)
+            .context("Failed to read the site's posts")?;
+
+        let redirect_repo = RedirectRepository::new()?;
+        let mut redirects = redirect_repo.list(site_id)?;
+        redirects.extend(redirect_repo.slug_redirects(site_id)?);
+        let headers = HeaderRepository::new()?.list(site_id)?;
+
+        Ok(SiteSource {
+            settings,
+            posts,
+            redirects,
+            headers,
+        })
+    }
+}
+
+/// SiteContext struct
+/// What templates see as `site`
+/// favicons: Link tags for the generated icons, empty when the site has no favicon
+/// highlight_css: Path of the code highlighting stylesheet, relative to the site root
+/// search_index: Path of the search index, relative to the site root
+#[derive(Serialize)]
+struct SiteContext {
+    name: String,
+    url: String,
+    favicons: Vec<FaviconLink>,
+    highlight_css: Option<String>,
+    search_index: String,
+}
+
+/// PostContext struct
+/// What templates see as `post`, and each item in `posts`
+/// url: Path of the post's page relative to the site root, prefix it with `root` in templates
+/// image_set: Resized versions of the header image, when it's one of the site's assets
+/// toc: The post's headings, nested by level
+/// stats: Word count, reading time and the like, e.g. `post.stats.reading_time`
+/// content: The post rendered to HTML
+/// text: The post's words without markup, only for the search index
+#[derive(Serialize)]
+struct PostContext {
+    title: String,
+    slug: String,
+    url: String,
+    date: String,
+    excerpt: String,
+    image: Option<String>,
+    image_set: Option<ImageSet>,
+    tags: Vec<String>,
+    toc: Vec<TocEntry>,
+    stats: PostStats,
+    content: String,
+    #[serde(skip)]
+    text: String,
+}
+
+/// TagContext struct
+/// Each item in `tags`, every tag used on the site with how many posts have it.
+/// A tag archive sees its own tag as `tag`
+/// url: Path of the tag's archive relative to the site root
+#[derive(Serialize)]
+struct TagContext {
+    name: String,
+    slug: String,
+    url: String,
+    count: usize,
+}
+
+/// Pagination struct
+/// What the index and tag archives see as `pagination`
+/// page: This page's number, starting at 1
+/// prev, next: Paths of the newer and older pages relative to the site root, none on the first and last page
+/// pages: Every page of the listing, for numbered links
+#[derive(Serialize)]
+struct Pagination {
+    page: usize,
+    total_pages: usize,
+    total_posts: usize,
+    per_page: usize,
+    prev: Option<String>,
+    next: Option<String>,
+    pages: Vec<PageLink>,
+}
+
+/// PageLink struct
+/// A page of a listing, url is relative to the site root
+#[derive(Serialize)]
+struct PageLink {
+    number: usize,
+    url: String,
+}
+
+/// Listing struct
+/// What every page of the index and the tag archives share
+/// per_page: Posts on each page, 0 puts them all on one
+struct Listing<'a> {
+    env: &'a Environment<'static>,
+    site: &'a SiteContext,
+    tags: &'a [TagContext],
+    now: &'a str,
+    per_page: usize,
+}
+
+/// Builds the site into out_dir, replacing anything that was there before
+///
+/// # Arguments
+///
+/// * `site` - The site being built
+/// * `source` - The site's settings, every post to render and its redirects
+/// * `out_dir` - Where the rendered site is written
+///
+/// # Returns
+///
+/// A Result containing the BuildOutput
+pub fn build_site(site: &SiteDetails, source: SiteSource, out_dir: &Path) -> Result<BuildOutput> {
+    println!(">> Building site into {}", out_dir.display());
+    let SiteSource {
+        settings,
+        posts,
+        redirects,
+        headers,
+    } = source;
+
+    // a bad header can take the whole site down, refuse to build with one
+    let headers = effective_headers(&headers, settings.security_headers);
+    let invalid: Vec<String> = headers
+        .iter()
+        .filter_map(|rule| rule.validate().err())
+        .collect();
+    if !invalid.is_empty() {
+        return Err(anyhow!("Invalid header rules: {}", invalid.join(", ")));
+    }
+    let theme = Theme::for_site(site, &settings.theme)?;
+
+    // start from an empty directory so deleted posts don't linger in the output
+    if out_dir.exists() {
+        fs::remove_dir_all(out_dir).context("Failed to clear the previous build")?;
+    }
+    let posts_dir = out_dir.join("posts");
+    fs::create_dir_all(&posts_dir).context("Failed to create the build's 'posts' directory")?;
+
+    // local images in posts go into the asset store first, so they get resized with the rest
+    let site_path = site.build_site_path()?;
+    let mut warnings = Vec::new();
+    let mut local_images = Vec::with_capacity(posts.len());
+    for post in &posts {
+        let images = import_local_images(&post.content, &site_path)
+            .with_context(|| format!("Failed to copy the images in {}", post.title))?;
+        for missing in &images.missing {
+            warnings.push(format!("{}: image not found: {}", post.title, missing));
+        }
+        local_images.push(images.urls);
+    }
+    let images = ImagePipeline::for_site(site)?.process_dir(&site_path.join("assets"), out_dir)?;
+
+    let mut shortcodes = Shortcodes::for_theme(&theme);
+    shortcodes.scripts = settings.html_policy == HtmlPolicy::Allow;
+    let mut pages = Vec::new();
+    let mut used_slugs = HashSet::new();
+    for (post, local_images) in posts.into_iter().zip(local_images) {
+        let slug = page_slug(&post, &mut used_slugs);
+        let rendered = md_to_html(&post, &settings, &local_images, &shortcodes);
+        for warning in &rendered.warnings {
+            warnings.push(format!("{}: {}", post.title, warning));
+        }
+        // post pages are one level down, under posts/
+        let content = responsive_html(&rendered.html, &images, "../");
+        let image = post.image.filter(|image| !image.is_empty());
+        pages.push(PostContext {
+            url: format!("posts/{}.html", slug),
+            slug,
+            title: post.title,
+            date: post.date,
+            // an excerpt written for the post wins over the automatic one
+            excerpt: match post.excerpt.trim() {
+                "" => rendered.excerpt,
+                excerpt => excerpt.to_string(),
+            },
+            image_set: image.as_ref().and_then(|image| images.get(image).cloned()),
+            image,
+            tags: post.tags.into_iter().filter(|tag| !tag.trim().is_empty()).collect(),
+            toc: rendered.toc,
+            // counted with the site's own Markdown extensions
+            stats: PostStats::from_markdown(&post.content, &settings.markdown),
+            text: plain_text(&post.content, &settings.markdown),
+            content,
+        });
+    }
+    // newest first
+    pages.sort_by_key(|post| std::cmp::Reverse(parse_post_date(&post.date)));
+
+    let documents: Vec<SearchDocument> = pages
+        .iter()
+        .map(|post| SearchDocument {
+            title: post.title.clone(),
+            url: post.url.clone(),
+            date: post.date.clone(),
+            excerpt: post.excerpt.clone(),
+            tags: post.tags.clone(),
+            text: post.text.clone(),
+        })
+        .collect();
+    SearchIndex::build(&documents).write(out_dir)?;
+
+    // the favicon saved with the site, or one dropped into its directory before that was possible
+    let favicon = match &site.favicon {
+        Some(bytes) if !bytes.is_empty() => Some(bytes.clone()),
+        _ => fs::read(site_path.join("favicon.ico")).ok(),
+    };
+    let favicons = match favicon {
+        Some(bytes) => write_favicons(&bytes, out_dir).unwrap_or_else(|e| {
+            warnings.push(format!("Favicon skipped: {:#}", e));
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+
+    let highlight_css = match settings.highlight_theme.as_str() {
+        "" => None,
+        name => {
+            let css = theme_css(name)?;
+            fs::write(out_dir.join(HIGHLIGHT_STYLESHEET), css)
+                .context("Failed to write the code highlighting stylesheet")?;
+            Some(HIGHLIGHT_STYLESHEET.to_string())
+        }
+    };
+
+    let env = theme.environment();
+    let site_context = SiteContext {
+        name: site.name.clone().unwrap_or_default(),
+        url: site.url.clone().unwrap_or_default(),
+        favicons,
+        highlight_css,
+        search_index: SEARCH_INDEX_FILE.to_string(),
+    };
+    let now = chrono::Local::now().to_rfc3339();
+
+    for post in &pages {
+        let html = render(
+            &env,
+            POST_TEMPLATE,
+            context! { site => &site_context, post, now, root => "../" },
+        )?;
+        fs::write(posts_dir.join(format!("{}.html", post.slug)), html)
+            .with_context(|| format!("Failed to write post {}", post.slug))?;
+    }
+
+    let tags = collect_tags(&pages);
+    let listing = Listing {
+        env: &env,
+        site: &site_context,
+        tags: &tags,
+        now: &now,
+        per_page: settings.posts_per_page,
+    };
+    let all_posts: Vec<&PostContext> = pages.iter().collect();
+    listing.write(out_dir, INDEX_TEMPLATE, "", &all_posts, None)?;
+    for tag in &tags {
+        let tagged: Vec<&PostContext> = pages
+            .iter()
+            .filter(|post| {
+                post.tags
+                    .iter()
+                    .any(|name| slugify(name.trim()) == tag.slug)
+            })
+            .collect();
+        listing.write(
+            out_dir,
+            TAG_TEMPLATE,
+            &format!("tags/{}/", tag.slug),
+            &tagged,
+            Some(tag),
+        )?;
+    }
+
+    theme.copy_static(out_dir)?;
+
+    if !redirects.is_empty() {
+        fs::write(out_dir.join("_redirects"), redirects_file(&redirects))
+            .context("Failed to write _redirects")?;
+    }
+    if !headers.is_empty() {
+        fs::write(out_dir.join("_headers"), headers_file(&headers))
+            .context("Failed to write _headers")?;
+    }
+
+    // static files kept alongside the site's posts
+    let assets = site_path.join("assets");
+    if assets.exists() {
+        copy_dir(&assets, &out_dir.join("assets"))?;
+    }
+
+    let files = list_files(out_dir)?;
+    println!(">> Built {} files", files.len());
+    for warning in &warnings {
+        println!(">> Warning: {}", warning);
+    }
+
+    Ok(BuildOutput {
+        root: out_dir.to_path_buf(),
+        files,
+        warnings,
+    })
+}
+
+fn render(env: &Environment, template: &str, ctx: minijinja::Value) -> Result<String> {
+    env.get_template(template)
+        .and_then(|template| template.render(ctx))
+        .with_context(|| format!("Failed to render {}", template))
+}
+
+/// The file name a post is written to. Saved posts already have a unique slug,
+/// posts that were never saved get one from their title
+fn page_slug(post: &Post, used: &mut HashSet<String>) -> String {
+    let base = match post.slug.as_str() {
+        "" => slugify(&post.title),
+        slug => slug.to_string(),
+    };
+    let base = if base.is_empty() {
+        FALLBACK_SLUG.to_string()
+    } else {
+        base
+    };
+
+    let mut slug = base.clone();
+    let mut suffix = 1;
+    while !used.insert(slug.to_lowercase()) {
+        suffix += 1;
+        slug = format!("{}-{}", base, suffix);
+    }
+    slug
+}
+
+/// Every tag on the site, sorted by name. Tags without a letter or digit can't have an archive and are left out
+fn collect_tags(posts: &[PostContext]) -> Vec<TagContext> {
+    let mut counts: BTreeMap<String, (String, usize)> = BTreeMap::new();
+    for tag in posts.iter().flat_map(|post| &post.tags) {
+        let tag = tag.trim();
+        let slug = slugify(tag);
+        if slug.is_empty() {
+            continue;
+        }
+        let entry = counts.entry(slug).or_insert_with(|| (tag.to_string(), 0));
+        entry.1 += 1;
+    }
+
+    counts
+        .into_iter()
+        .map(|(slug, (name, count))| TagContext {
+            name,
+            url: format!("tags/{}/index.html", slug),
+            slug,
+            count,
+        })
+        .collect()
+}
+
+impl Listing<'_> {
+    /// Writes a list of posts over as many pages as it needs, the first page at dir/index.html
+    /// and the others at dir/page/N/index.html
+    ///
+    /// # Arguments
+    ///
+    /// * `out_dir` - The build's root
+    /// * `template` - The template each page is rendered with
+    /// * `dir` - Where the listing goes relative to the site root, "" for the front page or e.g. "tags/rust/"
+    /// * `posts` - Every post in the listing, newest first
+    /// * `tag` - The tag a tag archive is for
+    fn write(
+        &self,
+        out_dir: &Path,
+        template: &str,
+        dir: &str,
+        posts: &[&PostContext],
+        tag: Option<&TagContext>,
+    ) -> Result<()> {
+        let per_page = match self.per_page {
+            0 => posts.len().max(1),
+            per_page => per_page,
+        };
+        // a listing without posts still gets its first page
+        let total_pages = posts.len().div_ceil(per_page).max(1);
+        let page_url = |page: usize| match page {
+            1 => format!("{}index.html", dir),
+            page => format!("{}page/{}/index.html", dir, page),
+        };
+
+        for page in 1..=total_pages {
+            let url = page_url(page);
+            let start = (page - 1) * per_page;
+            let page_posts = &posts[start.min(posts.len())..(start + per_page).min(posts.len())];
+            let pagination = Pagination {
+                page,
+                total_pages,
+                total_posts: posts.len(),
+                per_page,
+                prev: (page > 1).then(|| page_url(page - 1)),
+                next: (page < total_pages).then(|| page_url(page + 1)),
+                pages: (1..=total_pages)
+                    .map(|number| PageLink {
+                        number,
+                        url: page_url(number),
+                    })
+                    .collect(),
+            };
+            // one ../ for every directory the page is below the site root
+            let root = match url.matches('/').count() {
+                0 => String::from("./"),
+                depth => "../".repeat(depth),
+            };
+
+            let html = render(
+                self.env,
+                template,
+                context! {
+                    site => self.site,
+                    posts => page_posts,
+                    tags => self.tags,
+                    tag,
+                    pagination,
+                    now => self.now,
+                    root,
+                },
+            )?;
+            let path = out_dir.join(&url);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create the directory for {}", url))?;
+            }
+            fs::write(&path, html).with_context(|| format!("Failed to write {}", url))?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists every file under root, as slash-prefixed paths relative to it
+pub fn list_files(root: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let relative = path.strip_prefix(root)?;
+                let parts: Vec<String> = relative
+                    .components()
+                    .map(|part| part.as_os_str().to_string_lossy().to_string())
+                    .collect();
+                files.push(format!("/{}", parts.join("/")));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// The Content-Type a built file should be served with, based on its extension
+pub fn content_type_for(file: &str) -> &'static str {
+    let extension = file.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("woff") => "font/woff",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Recursively copies the contents of one directory into another
+pub fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        let target = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            fs::copy(&path, &target)
+                .with_context(|| format!("Failed to copy {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::AssetStore;
+
+    fn create_test_site() -> SiteDetails {
+        SiteDetails {
+            name: Some("Build Test".to_string()),
+            domain: None,
+            id: Some("build-test".to_string()),
+            ssl: None,
+            url: None,
+            screenshot_url: None,
+            password: None,
+            required: None,
+            favicon: None,
+            favicon_path: None,
+        }
+    }
+
+    #[test]
+    fn test_build_site_renders_theme() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let site = create_test_site();
+
+        let mut older = Post::new(String::from("First Post"));
+        older.date = String::from("2024/01/05 09:00 AM");
+        older.content = String::from(
+            "Hello from *January*.\n\n## Notes\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n~~old~~ news[^1]\n\n## Notes\n\n## Custom {#mine}\n\n### Deeper `code`\n\n[^1]: A footnote.\n",
+        );
+        older.tags = vec![String::from("Rust"), String::from("Tauri")];
+        older.image = Some(AssetStore::url_for("3f78.png"));
+        let mut newer = Post::new(String::from("Second Post"));
+        newer.date = String::from("2024/11/02 09:30 PM");
+        newer.tags = vec![String::from("Rust")];
+        newer.content = String::from(
+            "Intro with **bold**\ntext.\n\n## Part\n\nMore intro <!-- more -->\n\nHidden.\n\n![Lost](./lost.png)",
+        );
+
+        let source = SiteSource {
+            posts: vec![older, newer],
+            redirects: vec![RedirectRule::moved(
+                String::from("/posts/old.html"),
+                String::from("/posts/first-post.html"),
+            )],
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, dir.path())?;
+        assert_eq!(
+            build.files,
+            vec![
+                "/_headers",
+                "/_redirects",
+                "/highlight.css",
+                "/index.html",
+                "/posts/first-post.html",
+                "/posts/second-post.html",
+                "/search-index.json",
+                "/tags/rust/index.html",
+                "/tags/tauri/index.html"
+            ]
+        );
+        assert_eq!(
+            build.warnings,
+            vec!["Second Post: image not found: ./lost.png"]
+        );
+        let redirects = fs::read_to_string(build.path_of("/_redirects"))?;
+        assert!(redirects.contains("/posts/old.html /posts/first-post.html 301\n"));
+
+        // both files are part of what gets deployed to Netlify
+        let hashes = crate::netlify::Netlify::generate_sha1_for_posts(&build.root).unwrap();
+        assert!(hashes.files.contains_key("/_headers"));
+        assert!(hashes.files.contains_key("/_redirects"));
+
+        let index = fs::read_to_string(build.path_of("/index.html"))?;
+        assert!(index.contains("<title>Build Test</title>"));
+        let second = index.find("second-post.html").unwrap();
+        let first = index.find("first-post.html").unwrap();
+        assert!(second < first, "newest post comes first");
+        assert!(index.contains(r#"data-tag="tauri">Tauri</span>"#));
+        // posts without an excerpt get the first paragraph, or everything before <!-- more -->
+        assert!(index.contains(r#"<p class="card__excerpt">Hello from January.</p>"#));
+        assert!(index.contains(r#"<p class="card__excerpt">Intro with bold text. More intro</p>"#));
+        // asset URLs are relative to the page, so builds work from any path
+        assert!(index.contains(r#"<img src=".&#x2f;assets&#x2f;3f78.png""#));
+
+        let post = fs::read_to_string(build.path_of("/posts/first-post.html"))?;
+        assert!(post.contains("<p>Hello from <em>January</em>.</p>"));
+        assert!(post.contains("Published: January 5, 2024"));
+        assert!(post.contains("1 min read"));
+        // tables, footnotes and strikethrough are on by default, headings get anchors
+        assert!(post.contains("<td>1</td>"));
+        assert!(post.contains("<del>old</del>"));
+        assert!(post.contains(r#"class="footnote-definition""#));
+        assert!(post.contains(r#"<h2 id="notes">Notes</h2>"#));
+        assert!(post.contains(r#"<h2 id="notes-2">Notes</h2>"#));
+        assert!(post.contains(r#"<h2 id="mine">Custom</h2>"#));
+        // the table of contents is rendered into the page, nested by level
+        assert!(post.contains(r##"<li><a href="#notes-2">Notes</a></li>"##));
+        assert!(post.contains(r##"<li><a href="#mine">Custom</a><ul>"##));
+        assert!(post.contains(r##"<li><a href="#deeper-code">Deeper code</a></li>"##));
+        // autoescaping writes the slash in "../" as &#x2f;, which browsers read back as a slash
+        assert!(post.contains(r#"<a href="..&#x2f;">Home</a>"#));
+        assert!(post.contains(r#"<img src="..&#x2f;assets&#x2f;3f78.png""#));
+        assert!(post.contains(
+            r#"<a class="post__tag" href="..&#x2f;tags/rust/index.html" data-tag="rust">Rust</a>"#
+        ));
+
+        // tag archives list the tagged posts, two levels down
+        let tauri = fs::read_to_string(build.path_of("/tags/tauri/index.html"))?;
+        assert!(tauri.contains("Posts tagged Tauri"));
+        assert!(tauri.contains(r#"href="..&#x2f;..&#x2f;posts&#x2f;first-post.html""#));
+        assert!(!tauri.contains("second-post.html"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_site_paginates_index_and_tags() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let site = create_test_site();
+
+        let posts = (1..=5)
+            .map(|day| {
+                let mut post = Post::new(format!("Day {}", day));
+                post.date = format!("2024/03/0{} 09:00 AM", day);
+                post.content = format!("Notes from day {}.", day);
+                if day % 2 == 1 {
+                    post.tags = vec![String::from("Odd")];
+                }
+                post
+            })
+            .collect();
+        let source = SiteSource {
+            settings: SiteSettings {
+                posts_per_page: 2,
+                ..SiteSettings::default()
+            },
+            posts,
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, dir.path())?;
+        for page in [
+            "/index.html",
+            "/page/2/index.html",
+            "/page/3/index.html",
+            "/tags/odd/index.html",
+            "/tags/odd/page/2/index.html",
+        ] {
+            assert!(build.files.iter().any(|file| file == page), "{}", page);
+        }
+        assert!(!build.files.iter().any(|file| file == "/page/4/index.html"));
+        assert!(!build
+            .files
+            .iter()
+            .any(|file| file == "/tags/odd/page/3/index.html"));
+
+        // newest first, two to a page
+        let first = fs::read_to_string(build.path_of("/index.html"))?;
+        assert!(first.contains("day-5.html") && first.contains("day-4.html"));
+        assert!(!first.contains("day-3.html"));
+        assert!(first.contains(r#"rel="next" href=".&#x2f;page&#x2f;2&#x2f;index.html""#));
+        assert!(!first.contains(r#"rel="prev""#));
+
+        let second = fs::read_to_string(build.path_of("/page/2/index.html"))?;
+        assert!(second.contains("day-3.html") && second.contains("day-2.html"));
+        assert!(second.contains(r#"href="..&#x2f;..&#x2f;posts&#x2f;day-3.html""#));
+        assert!(second.contains(r#"rel="prev" href="..&#x2f;..&#x2f;index.html""#));
+        assert!(second.contains(r#"rel="next" href="..&#x2f;..&#x2f;page&#x2f;3&#x2f;index.html""#));
+        assert!(second.contains(r#"<span aria-current="page">2</span>"#));
+
+        let last = fs::read_to_string(build.path_of("/page/3/index.html"))?;
+        assert!(last.contains("day-1.html"));
+        assert!(!last.contains(r#"rel="next""#));
+
+        // Day 5 and Day 3 on the first page of the tag, Day 1 on the second
+        let odd = fs::read_to_string(build.path_of("/tags/odd/page/2/index.html"))?;
+        assert!(odd.contains("Posts tagged Odd"));
+        assert!(odd.contains("day-1.html") && !odd.contains("day-3.html"));
+        assert!(odd.contains(
+            r#"rel="prev" href="..&#x2f;..&#x2f;..&#x2f;..&#x2f;tags&#x2f;odd&#x2f;index.html""#
+        ));
+
+        // 0 puts every post on the front page
+        let source = SiteSource {
+            settings: SiteSettings {
+                posts_per_page: 0,
+                ..SiteSettings::default()
+            },
+            posts: vec![Post::new(String::from("Only"))],
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, dir.path())?;
+        assert!(!build.files.iter().any(|file| file.starts_with("/page/")));
+        let index = fs::read_to_string(build.path_of("/index.html"))?;
+        assert!(!index.contains(r#"class="pagination""#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_site_escapes_user_content() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let site = create_test_site();
+
+        let mut post = Post::new(String::from("Guest <script>alert(1)</script>"));
+        post.excerpt = String::from(r#"Quotes " and <b>tags</b>"#);
+        post.image = Some(String::from("javascript:alert(1)"));
+        post.content = String::from(
+            "Hi <img src=x onerror=alert(1)> [click](javascript:alert(1))\n\n<script>alert(2)</script>\n\nCosts $5, $a<b$ and $$\\frac{1}{2} \\evil$$\n\n{{< youtube dQw4w9WgXcQ >}}\n\n<a title=\"\u{E000}0\u{E001}\">x</a>\n",
+        );
+        let page = |settings: &SiteSettings| -> Result<(String, String)> {
+            let source = SiteSource {
+                settings: settings.clone(),
+                posts: vec![post.clone()],
+                ..SiteSource::default()
+            };
+            let build = build_site(&site, source, dir.path())?;
+            let index = fs::read_to_string(build.path_of("/index.html"))?;
+            let post_file = build.files.iter().find(|file| file.starts_with("/posts/")).unwrap();
+            Ok((index, fs::read_to_string(build.path_of(post_file))?))
+        };
+
+        let (index, allowed) = page(&SiteSettings::default())?;
+        assert!(!index.contains("<script>alert(1)"));
+        assert!(index.contains("Guest &lt;script&gt;"));
+        assert!(index.contains("Quotes &quot; and &lt;b&gt;"));
+        assert!(!index.contains("javascript:"));
+        // trusted sites keep their HTML
+        assert!(allowed.contains("<script>alert(2)</script>"));
+        // math is off unless the site turns it on
+        assert!(allowed.contains("Costs $5, $a&lt;b$"));
+
+        let mut settings = SiteSettings {
+            html_policy: HtmlPolicy::Strip,
+            ..SiteSettings::default()
+        };
+        let (_, stripped) = page(&settings)?;
+        assert!(!stripped.contains("<script>alert(2)"));
+        assert!(!stripped.contains("onerror"));
+        assert!(stripped.contains(r##"<a href="#">click</a>"##));
+
+        settings.html_policy = HtmlPolicy::Sanitize;
+        let (_, sanitized) = page(&settings)?;
+        assert!(!sanitized.contains("<script>alert(2)"));
+        assert!(!sanitized.contains("onerror"));
+        assert!(!sanitized.contains("javascript:"));
+        assert!(sanitized.contains(r#"<img src="x">"#));
+        // embeds from shortcodes are generated, not written by the author, so they're kept
+        assert!(sanitized.contains(
+            r#"<div class="shortcode-youtube"><iframe src="https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ""#
+        ));
+        // a placeholder typed by the author doesn't pull the embed into an attribute
+        assert_eq!(sanitized.matches("youtube-nocookie.com/embed").count(), 1);
+        assert!(!sanitized.contains('\u{E000}'));
+
+        // formulas are MathML the sanitizer doesn't touch, their source still escaped
+        settings.markdown.math = true;
+        let (_, math) = page(&settings)?;
+        assert!(math.contains("Costs $5, <math"));
+        assert!(math.contains("<mi>a</mi><mo>&lt;</mo><mi>b</mi>"));
+        assert!(math.contains(r#"<code class="math-error">$$\frac{1}{2} \evil$$</code>"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_site_keeps_block_shortcodes_out_of_paragraphs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let site = create_test_site();
+
+        let mut post = Post::new(String::from("Videos"));
+        post.content = String::from(
+            "Two in a row:\n{{< youtube dQw4w9WgXcQ >}}\n{{< youtube 9bZkp7q19f0 >}}\nThat is all.\n",
+        );
+        let source = SiteSource {
+            posts: vec![post],
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, dir.path())?;
+        let post_file = build.files.iter().find(|file| file.starts_with("/posts/")).unwrap();
+        let html = fs::read_to_string(build.path_of(post_file))?;
+
+        assert_eq!(html.matches("youtube-nocookie.com/embed").count(), 2);
+        assert!(html.contains("<p>Two in a row:</p>\n<div class=\"shortcode-youtube\">"));
+        assert!(html.contains("</div>\n<p>That is all.</p>"));
+        assert!(!html.contains("<p><div") && !html.contains("</div></p>"));
+        Ok(())
+    }
+}
@@ -1,17 +1,24 @@
-use crate::driftwood::{md_to_html, template_html, NewSite, Post, SiteDetails};
+use crate::assets::AssetStore;
+use crate::builder::{build_site, SiteSource};
+use crate::deploy::DeployTargetConfig;
+use crate::driftwood::{NewSite, Post, SiteDetails, SiteSettings};
+use crate::headers::{effective_headers, HeaderRepository, HeaderRule};
+use crate::highlight::{self, theme_css};
 use crate::netlify::Netlify;
 use crate::posts::PostRepository;
+use crate::preview::{self, Rebuild};
+use crate::redirects::{RedirectRepository, RedirectRule};
 use crate::response::{
     CreateSiteResponse, // Request,
     Response,
 };
 use crate::sites::SiteRepository;
+use crate::themes::{self, Theme, DEFAULT_THEME};
+use crate::wordpress::import_wxr;
 use std::path::Path;
 
 pub const RECENT_POST_LIMIT: i32 = 5;
 
-/// TODO in edit post screen, add field for image, tags, and create a way to extract an excerpt from the post's contents.
-
 #[tauri::command]
 pub fn netlify_login() -> Response {
     println!("Logging in");
@@ -65,6 +72,26 @@ pub fn create_site(new_site: &str) -> Response {
         Ok(site_details) => {
             refresh_sites(false);
 
+            // remember the theme picked in the new site form
+            if let Some(site_id) = &site_details.id {
+                let theme = match Theme::load(&site.template) {
+                    Ok(_) => site.template.clone(),
+                    Err(e) => {
+                        println!("> {}, using the {} theme", e, DEFAULT_THEME);
+                        DEFAULT_THEME.to_string()
+                    }
+                };
+                let settings = SiteSettings {
+                    theme,
+                    ..SiteSettings::default()
+                };
+                let saved = SiteRepository::new()
+                    .and_then(|repo| repo.update_settings(site_id, &settings));
+                if let Err(e) = saved {
+                    eprintln!("Failed to save settings for the new site: {}", e);
+                }
+            }
+
             let create_site_response = CreateSiteResponse {
                 name: site_details.name,
                 title: Some(String::from("Created")),
@@ -264,22 +291,34 @@ pub fn update_post(post_data: String, site_data: String) -> Response {
     // manually set the content
     updated_post.content = post_data.content;
     updated_post.post_id = post_data.post_id;
+    updated_post.tags = post_data.tags;
+    // left empty, the build makes one from the content
+    updated_post.excerpt = post_data.excerpt.trim().to_string();
+    // empty keeps the current slug, the repository makes a new one if the title changed
+    updated_post.slug = post_data.slug;
+
+    let site_id = site_data
+        .id
+        .expect("Failed to retrieve site id in update_post");
+    // the post's statistics are counted with the site's Markdown extensions
+    let settings = match read_site_settings(&site_id) {
+        Ok(settings) => settings,
+        Err(e) => return Response::fail(e),
+    };
 
-    // strip bad chars and set post.filename
-    let _ = updated_post.clean_filename();
-    // (replace "-"" with spaces basically) and set post.title
-    let _ = updated_post.build_post_name();
+    updated_post.image = match import_header_image(&site_id, post_data.image) {
+        Ok(image) => image,
+        Err(e) => return Response::fail(e),
+    };
 
     // create post in DB
-    match post_repo.update(
-        &updated_post,
-        &site_data
-            .id
-            .expect("Failed to retrieve site id in update_post"),
-    ) {
-        Ok(()) => {
+    match post_repo.update(&updated_post, &site_id, &settings.markdown) {
+        Ok(slug) => {
             println!("Post updated in DB");
-            Response::success(String::from("success"))
+            preview::notify_site_changed(&site_id);
+            let mut response = Response::success(String::from("success"));
+            response.body = Some(serde_json::json!({ "slug": slug }));
+            response
         }
         Err(err) => {
             println!("Failed to update post in DB: {}", err);
@@ -325,22 +364,34 @@ pub fn create_post(post_data: String, site_data: String) -> Response {
     let mut new_post = Post::new(post_data.title);
     // manually set the content
     new_post.content = post_data.content;
+    new_post.tags = post_data.tags;
+    // left empty, the build makes one from the content
+    new_post.excerpt = post_data.excerpt.trim().to_string();
+    // the title is kept as written, the repository makes the slug from it when this is empty
+    new_post.slug = post_data.slug;
+
+    let site_id = site_data
+        .id
+        .expect("Failed to retrieve site id in create_post");
+    // the post's statistics are counted with the site's Markdown extensions
+    let settings = match read_site_settings(&site_id) {
+        Ok(settings) => settings,
+        Err(e) => return Response::fail(e),
+    };
 
-    // strip bad chars and set post.filename
-    let _ = new_post.clean_filename();
-    // (replace "-"" with spaces basically) and set post.title
-    let _ = new_post.build_post_name();
+    new_post.image = match import_header_image(&site_id, post_data.image) {
+        Ok(image) => image,
+        Err(e) => return Response::fail(e),
+    };
 
     // create post in DB
-    match post_repo.create(
-        &new_post,
-        &site_data
-            .id
-            .expect("Failed to retrieve site id in create_post"),
-    ) {
-        Ok(()) => {
+    match post_repo.create(&new_post, &site_id, &settings.markdown) {
+        Ok(slug) => {
             println!("Post created in DB");
-            Response::success(String::from("success"))
+            preview::notify_site_changed(&site_id);
+            let mut response = Response::success(String::from("success"));
+            response.body = Some(serde_json::json!({ "slug": slug }));
+            response
         }
         Err(err) => {
             println!("Failed to create post in DB: {}", err);
@@ -349,6 +400,47 @@ pub fn create_post(post_data: String, site_data: String) -> Response {
     }
 }
 
+/// Copies a header image picked from disk into the site's assets
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+/// * `file_path` a string, path to the image file
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the image's URL to save on the post
+#[tauri::command]
+pub fn import_post_image(site_id: String, file_path: String) -> Response {
+    println!("Importing image {} into site {}", file_path, site_id);
+
+    if !Path::new(&file_path).is_file() {
+        return Response::fail(format!("No image found at {}", file_path));
+    }
+
+    match import_header_image(&site_id, Some(file_path)) {
+        Ok(image) => {
+            let mut response = Response::success(String::from("Image imported"));
+            response.body = Some(serde_json::json!({ "image": image }));
+            response
+        }
+        Err(e) => Response::fail(e),
+    }
+}
+
+/// Moves a post's header image into the site's asset store when it points at a local file
+fn import_header_image(site_id: &str, image: Option<String>) -> Result<Option<String>, String> {
+    let image = match image.map(|image| image.trim().to_string()) {
+        Some(image) if !image.is_empty() => image,
+        _ => return Ok(None),
+    };
+
+    AssetStore::for_site_id(site_id)
+        .and_then(|store| store.import_image(&image))
+        .map(Some)
+        .map_err(|e| format!("Failed to import header image: {}", e))
+}
+
 /// Retrieves post data so you can edit existing posts
 ///
 /// # Arguments
@@ -416,136 +508,29 @@ pub fn get_recent_posts(site_id: String) -> Response {
 #[tauri::command]
 pub fn deploy_site(site_id: String) -> Response {
     println!("Deployed site: {} ", &site_id);
-    match Netlify::new() {
-        Ok(netlify) => {
-            let site = read_site(&site_id)
-                .expect("Failed to read site details from DB (result) in deploy_site")
-                .expect("Failed to read site details from DB (option) in deploy_site");
-
-            // new behavior: read site from DB, get all posts, iterate through them
-            // and build the HTML files, save to disk.
-
-            // retrieve all the posts
-            let post_repo =
-                PostRepository::new().expect("Failed to init post repository in deploy_site");
-            let posts = post_repo.list_all(&site_id);
-
-            // get posts out of the result
-            let posts = posts.expect(&format!(
-                "Failed to retrieve posts for site id {} ",
-                &site_id
-            ));
-
-            // build paths to the site and posts directories on disk
-            let site_path = SiteDetails::build_site_path(&site).expect("Failed to build site path");
-
-            if !site_path.exists() {
-                std::fs::create_dir_all(&site_path)
-                    .expect(&format!("Failed to create site id {} directory", &site_id));
-            }
-
-            let site_path = site_path.to_string_lossy().to_string();
-
-            let html_post_path = format!("{}/posts", site_path);
-            let html_post_path = Path::new(&html_post_path);
-            if !html_post_path.exists() {
-                std::fs::create_dir_all(html_post_path).expect(&format!(
-                    "Failed to create site id {} 'posts' directory",
-                    &site_id
-                ));
-            }
-
-            let mut html_file_names = vec![];
-
-            // iterate over the vector
-            posts.into_iter().for_each(|mut post| {
-                println!("post title: {}", post.title);
-                post.clean_filename()
-                    .expect("Failed to build post filename");
-                let html_file_name = format!("{}/posts/{}.html", &site_path, &post.filename,);
-                println!("Html file name: {} ", html_file_name);
-                // convert to HTML
-                md_to_html(&post, &html_file_name).expect(&format!(
-                    "failed to convert md to html, post name: {}",
-                    post.filename
-                ));
-                // save the file name for sending to Netlify
-                html_file_names.push(html_file_name);
-            });
-
-            if !template_html(html_file_names, &site_path, site.name.as_ref().unwrap()).unwrap() {
-                println!("Failed to template the HTML files, please review them.");
-                return Response::fail(String::from(
-                    "Failed to template the HTML files, please review them.",
-                ));
-            }
-
-            // generate the SHA1 hash
-            let site_path = Path::new(&site_path);
-
-            let sha1_result = Netlify::generate_sha1_for_posts(site_path, html_post_path);
+    deploy_site_to_target(&site_id, DeployTargetConfig::Netlify)
+}
 
-            if sha1_result.is_ok() {
-                println!("> SHA1 hash generated successfully.");
-            } else {
-                let sha_error = sha1_result.err().unwrap();
-                println!("> Error: {}", sha_error);
-                return Response::fail(String::from(
-                    "Failed to delete site, error in response from Netlify",
-                ));
-            }
+/// Builds a site and publishes it to a deploy target other than Netlify
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+/// * `target` a JSON string describing the target, e.g. {"kind": "folder", "path": "/home/me/site"}
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the DeployReport
+#[tauri::command]
+pub fn deploy_site_to(site_id: String, target: String) -> Response {
+    println!("Deploying site {} to {}", site_id, target);
 
-            // unwrap the result to get the FileHashes struct
-            let sha1_hashmap = sha1_result.unwrap();
-
-            // post the file hashes to netlify
-            let new_site = netlify.send_file_checksums(site.clone(), &sha1_hashmap);
-
-            // make sure you don't overlap "site" and "new site"
-            // site is the og site details, new site is the deploy details + site details
-            // the id will overlap
-            match new_site {
-                Ok(new_site) => {
-                    println!(">Site Details:");
-                    println!("{:?}", new_site);
-
-                    // loop over the site's required vector (unwrap to get outside the option)
-                    new_site.required.unwrap().iter().for_each(|file| {
-                        println!("> Required file: {:?}", file);
-
-                        // loop through our hashmap of file hashes
-                        sha1_hashmap.files.iter().for_each(|file_hash| {
-                            // destructure the tuple
-                            let (current_file_name, current_file_hash) = file_hash;
-                            // if they match, print
-                            if file == current_file_hash {
-                                println!("> Matching File hash: {:?}", file_hash);
-                                let response = netlify.upload_file(
-                                    site.id.clone().unwrap(),
-                                    new_site.id.clone().unwrap(),
-                                    Path::new(current_file_name),
-                                );
-                                match response {
-                                    Ok(_) => {
-                                        println!("> File uploaded successfully.");
-                                    }
-                                    Err(e) => {
-                                        println!("> Error: {:?}", e);
-                                    }
-                                }
-                            }
-                        });
-                    });
-                }
-                Err(e) => {
-                    println!("Error: {:?}", e);
-                }
-            }
+    let target: DeployTargetConfig = match serde_json::from_str(&target) {
+        Ok(target) => target,
+        Err(e) => return Response::fail(format!("Invalid deploy target: {}", e)),
+    };
 
-            Response::success(String::from("Deployed site successfully! 🚀"))
-        }
-        Err(e) => Response::fail(format!("Failed to deploy site: {}", e.to_string())),
-    }
+    deploy_site_to_target(&site_id, target)
 }
 
 #[tauri::command]
@@ -581,7 +566,10 @@ pub fn delete_post(site_id: String, post_name: String) -> Response {
                 .join(format!("{}.md", post.filename));
 
             match std::fs::remove_file(post_path) {
-                Ok(_) => Response::success(String::from("Post deleted successfully")),
+                Ok(_) => {
+                    preview::notify_site_changed(&site_id);
+                    Response::success(String::from("Post deleted successfully"))
+                }
                 Err(e) => Response::fail(format!("Failed to delete post: {}", e)),
             }
         }
@@ -606,6 +594,114 @@ pub fn get_post_count(site_id: &str) -> Response {
     response
 }
 
+/// Full-text search over post titles, content, excerpts and tags
+///
+/// # Arguments
+///
+/// * `site_id` the ID of the website to search, leave it out to search every site
+/// * `query` a string, the words to look for
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the SearchResults, best matches first
+#[tauri::command]
+pub fn search_posts(site_id: Option<String>, query: String) -> Response {
+    println!("Searching posts in {:?} for: {}", site_id, query);
+
+    let post_repo = PostRepository::new().expect("Failed to init post repository in search_posts");
+    match post_repo.search(site_id.as_deref(), &query) {
+        Ok(results) => {
+            let mut response = Response::success(format!("Found {} posts", results.len()));
+            response.body = Some(
+                serde_json::to_value(results)
+                    .expect("Failed to serialize SearchResults in search_posts"),
+            );
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to search posts: {}", e)),
+    }
+}
+
+/// Adds up the word counts, reading times and other content statistics of a site's posts
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the SiteStats
+#[tauri::command]
+pub fn get_site_stats(site_id: &str) -> Response {
+    println!("Getting content statistics for site {}", site_id);
+
+    let post_repo = PostRepository::new().expect("Failed to init post repository in get_site_stats");
+    match post_repo.site_stats(site_id) {
+        Ok(stats) => {
+            let mut response = Response::success(format!(
+                "{} words in {} posts",
+                stats.word_count, stats.post_count
+            ));
+            response.body = Some(
+                serde_json::to_value(stats)
+                    .expect("Failed to serialize SiteStats in get_site_stats"),
+            );
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to read site statistics: {}", e)),
+    }
+}
+
+/// Imports the published posts from a WordPress export file into a site
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website to create the posts in
+/// * `file_path` a string, path to the WXR file exported from WordPress
+/// * `download_images` whether to download images into the site's assets,
+///   when false they're all flagged for manual handling
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the ImportReport
+#[tauri::command]
+pub fn import_wordpress(site_id: String, file_path: String, download_images: bool) -> Response {
+    println!("Importing WordPress export {} into site {}", file_path, site_id);
+
+    let site = match read_site(&site_id) {
+        Ok(Some(site)) => site,
+        Ok(None) => return Response::fail(String::from("No site by that ID found in database")),
+        Err(e) => return Response::fail(e),
+    };
+
+    let settings = match read_site_settings(&site_id) {
+        Ok(settings) => settings,
+        Err(e) => return Response::fail(e),
+    };
+    let post_repo =
+        PostRepository::new().expect("Failed to init post repository in import_wordpress");
+
+    match import_wxr(
+        Path::new(&file_path),
+        &site,
+        &post_repo,
+        &settings.markdown,
+        download_images,
+    ) {
+        Ok(report) => {
+            preview::notify_site_changed(&site_id);
+            let mut response =
+                Response::success(format!("Imported {} posts from WordPress", report.imported));
+            response.body = Some(
+                serde_json::to_value(report)
+                    .expect("Failed to serialize ImportReport in import_wordpress"),
+            );
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to import WordPress export: {}", e)),
+    }
+}
+
 /// Get all the sites for the user
 /// netlify: A Netlify instance
 /// Returns a vector of SiteDetails
@@ -626,6 +722,383 @@ fn get_sites(netlify: Netlify) -> Vec<SiteDetails> {
     }
 }
 
+/// Builds a site into a temporary folder and serves it on localhost,
+/// open pages reload whenever a post or the site's files change
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the preview's URL
+#[tauri::command]
+pub fn start_preview(site_id: String) -> Response {
+    println!("Starting preview for site {}", site_id);
+
+    let site = match read_site(&site_id) {
+        Ok(Some(site)) => site,
+        Ok(None) => return Response::fail(String::from("No site by that ID found in database")),
+        Err(e) => return Response::fail(e),
+    };
+
+    let mut watch = match site.build_site_path() {
+        Ok(site_path) => vec![site_path.join("assets"), site_path.join("favicon.ico")],
+        Err(e) => return Response::fail(format!("Failed to build site path: {}", e)),
+    };
+    // editing the theme or the site's template overrides refreshes the preview too
+    let theme = read_site_settings(&site_id)
+        .map_err(|e| anyhow::anyhow!(e))
+        .and_then(|settings| Theme::for_site(&site, &settings.theme));
+    match theme {
+        Ok(theme) => watch.extend(theme.watch_paths()),
+        Err(e) => return Response::fail(format!("Failed to load the site's theme: {}", e)),
+    }
+
+    let rebuild_site_id = site_id.clone();
+    let rebuild: Rebuild = Box::new(move |dir| {
+        let site = read_site(&rebuild_site_id)
+            .map_err(|e| anyhow::anyhow!(e))?
+            .ok_or_else(|| anyhow::anyhow!("Site {} no longer exists", rebuild_site_id))?;
+        build_site(&site, SiteSource::load(&rebuild_site_id)?, dir)?;
+        Ok(())
+    });
+
+    match preview::start_site_preview(&site_id, rebuild, watch) {
+        Ok(url) => {
+            // don't handle this, the URL is in the response if the browser doesn't open
+            let _ = webbrowser::open(&url);
+            let mut response = Response::success(format!("Preview running at {}", url));
+            response.body = Some(serde_json::json!({ "url": url }));
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to start preview: {}", e)),
+    }
+}
+
+/// Stops a site's preview server and removes the preview build
+#[tauri::command]
+pub fn stop_preview(site_id: String) -> Response {
+    println!("Stopping preview for site {}", site_id);
+
+    if preview::stop_site_preview(&site_id) {
+        Response::success(String::from("Preview stopped"))
+    } else {
+        Response::fail(String::from("No preview is running for this site"))
+    }
+}
+
+/// Lists the installed themes a site can use
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains each theme's manifest
+#[tauri::command]
+pub fn list_themes() -> Response {
+    println!("Listing themes");
+
+    match themes::list_themes() {
+        Ok(themes) => {
+            let mut response = Response::success(format!("Found {} themes", themes.len()));
+            response.body = Some(
+                serde_json::to_value(themes).expect("Failed to serialize themes in list_themes"),
+            );
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to list themes: {}", e)),
+    }
+}
+
+/// Lists the colour schemes code blocks can be highlighted with
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the theme names
+#[tauri::command]
+pub fn list_highlight_themes() -> Response {
+    println!("Listing highlight themes");
+
+    let themes = highlight::list_highlight_themes();
+    let mut response = Response::success(format!("Found {} highlight themes", themes.len()));
+    response.body = Some(serde_json::json!(themes));
+    response
+}
+
+/// Retrieves the build settings for a site
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the SiteSettings
+#[tauri::command]
+pub fn get_site_settings(site_id: String) -> Response {
+    println!("Getting settings for site {}", site_id);
+
+    match read_site_settings(&site_id) {
+        Ok(settings) => {
+            let mut response = Response::success(String::from("Retrieved site settings"));
+            response.body = Some(
+                serde_json::to_value(settings)
+                    .expect("Failed to serialize SiteSettings in get_site_settings"),
+            );
+            response
+        }
+        Err(e) => Response::fail(e),
+    }
+}
+
+/// Saves the build settings for a site
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+/// * `settings` a JSON string of the SiteSettings, e.g. {"theme": "default"}
+#[tauri::command]
+pub fn update_site_settings(site_id: String, settings: String) -> Response {
+    println!("Updating settings for site {}: {}", site_id, settings);
+
+    let settings: SiteSettings = match serde_json::from_str(&settings) {
+        Ok(settings) => settings,
+        Err(e) => return Response::fail(format!("Invalid site settings: {}", e)),
+    };
+
+    if let Err(e) = Theme::load(&settings.theme) {
+        return Response::fail(format!("Can't use that theme: {}", e));
+    }
+    if !settings.highlight_theme.is_empty() {
+        if let Err(e) = theme_css(&settings.highlight_theme) {
+            return Response::fail(format!("Can't use that highlight theme: {}", e));
+        }
+    }
+
+    let result = SiteRepository::new().and_then(|repo| repo.update_settings(&site_id, &settings));
+    match result {
+        Ok(()) => {
+            preview::notify_site_changed(&site_id);
+            Response::success(String::from("Site settings saved"))
+        }
+        Err(e) => Response::fail(format!("Failed to save site settings: {}", e)),
+    }
+}
+
+/// Lists a site's custom redirect and rewrite rules
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the custom rules followed by the
+/// redirects generated from renamed posts, which have an id of 0
+#[tauri::command]
+pub fn list_redirects(site_id: String) -> Response {
+    println!("Listing redirects for site {}", site_id);
+
+    let rules = RedirectRepository::new().and_then(|repo| {
+        let mut rules = repo.list(&site_id)?;
+        rules.extend(repo.slug_redirects(&site_id)?);
+        Ok(rules)
+    });
+
+    match rules {
+        Ok(rules) => {
+            let mut response = Response::success(format!("Found {} redirects", rules.len()));
+            response.body = Some(
+                serde_json::to_value(rules).expect("Failed to serialize redirects in list_redirects"),
+            );
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to read redirects: {}", e)),
+    }
+}
+
+/// Adds a custom redirect or rewrite rule to a site, it's deployed with the site's next deploy
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+/// * `rule` a JSON string of the rule, e.g. {"from": "/old", "to": "/new", "status": 301}
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the saved rule
+#[tauri::command]
+pub fn add_redirect(site_id: String, rule: String) -> Response {
+    println!("Adding redirect to site {}: {}", site_id, rule);
+
+    let mut rule: RedirectRule = match serde_json::from_str(&rule) {
+        Ok(rule) => rule,
+        Err(e) => return Response::fail(format!("Invalid redirect rule: {}", e)),
+    };
+    if let Err(e) = rule.validate() {
+        return Response::fail(format!("Invalid redirect rule: {}", e));
+    }
+
+    match RedirectRepository::new().and_then(|repo| repo.create(&site_id, &rule)) {
+        Ok(id) => {
+            rule.id = id;
+            preview::notify_site_changed(&site_id);
+            let mut response = Response::success(String::from("Redirect added"));
+            response.body = Some(
+                serde_json::to_value(rule).expect("Failed to serialize redirect in add_redirect"),
+            );
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to save redirect: {}", e)),
+    }
+}
+
+/// Removes one of a site's custom redirect rules
+#[tauri::command]
+pub fn delete_redirect(site_id: String, redirect_id: u64) -> Response {
+    println!("Deleting redirect {} from site {}", redirect_id, site_id);
+
+    match RedirectRepository::new().and_then(|repo| repo.delete(&site_id, redirect_id)) {
+        Ok(()) => {
+            preview::notify_site_changed(&site_id);
+            Response::success(String::from("Redirect deleted"))
+        }
+        Err(e) => Response::fail(format!("Failed to delete redirect: {}", e)),
+    }
+}
+
+/// Lists the HTTP headers a site is deployed with
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the site's custom rules and
+/// the default headers it uses, which have an id of 0
+#[tauri::command]
+pub fn list_headers(site_id: String) -> Response {
+    println!("Listing headers for site {}", site_id);
+
+    let settings = match read_site_settings(&site_id) {
+        Ok(settings) => settings,
+        Err(e) => return Response::fail(e),
+    };
+
+    match HeaderRepository::new().and_then(|repo| repo.list(&site_id)) {
+        Ok(rules) => {
+            let rules = effective_headers(&rules, settings.security_headers);
+            let mut response = Response::success(format!("Found {} headers", rules.len()));
+            response.body = Some(
+                serde_json::to_value(rules).expect("Failed to serialize headers in list_headers"),
+            );
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to read headers: {}", e)),
+    }
+}
+
+/// Adds a custom header rule to a site, it's deployed with the site's next deploy
+///
+/// # Arguments
+///
+/// * `site_id` a string, the ID of the website
+/// * `rule` a JSON string of the rule, e.g. {"path": "/*", "name": "Cache-Control", "value": "no-cache"}
+///
+/// # Returns
+///
+/// A Drift Response struct, the body contains the saved rule
+#[tauri::command]
+pub fn add_header(site_id: String, rule: String) -> Response {
+    println!("Adding header to site {}: {}", site_id, rule);
+
+    let mut rule: HeaderRule = match serde_json::from_str(&rule) {
+        Ok(rule) => rule,
+        Err(e) => return Response::fail(format!("Invalid header rule: {}", e)),
+    };
+    if let Err(e) = rule.validate() {
+        return Response::fail(format!("Invalid header rule: {}", e));
+    }
+
+    match HeaderRepository::new().and_then(|repo| repo.create(&site_id, &rule)) {
+        Ok(id) => {
+            rule.id = id;
+            preview::notify_site_changed(&site_id);
+            let mut response = Response::success(String::from("Header added"));
+            response.body = Some(
+                serde_json::to_value(rule).expect("Failed to serialize header in add_header"),
+            );
+            response
+        }
+        Err(e) => Response::fail(format!("Failed to save header: {}", e)),
+    }
+}
+
+/// Removes one of a site's custom header rules
+#[tauri::command]
+pub fn delete_header(site_id: String, header_id: u64) -> Response {
+    println!("Deleting header {} from site {}", header_id, site_id);
+
+    match HeaderRepository::new().and_then(|repo| repo.delete(&site_id, header_id)) {
+        Ok(()) => {
+            preview::notify_site_changed(&site_id);
+            Response::success(String::from("Header deleted"))
+        }
+        Err(e) => Response::fail(format!("Failed to delete header: {}", e)),
+    }
+}
+
+/// Builds a site from the posts in the DB and publishes it to the target
+fn deploy_site_to_target(site_id: &str, target: DeployTargetConfig) -> Response {
+    let site = match read_site(site_id) {
+        Ok(Some(site)) => site,
+        Ok(None) => return Response::fail(String::from("No site by that ID found in database")),
+        Err(e) => return Response::fail(e),
+    };
+
+    let target = match target.into_target() {
+        Ok(target) => target,
+        Err(e) => return Response::fail(format!("Failed to deploy site: {}", e)),
+    };
+
+    // retrieve the settings, posts and redirects
+    let source = match SiteSource::load(site_id) {
+        Ok(source) => source,
+        Err(e) => {
+            return Response::fail(format!(
+                "Failed to retrieve posts for site id {}: {}",
+                site_id, e
+            ))
+        }
+    };
+
+    let build = site
+        .build_output_path()
+        .and_then(|out_dir| build_site(&site, source, &out_dir));
+    let build = match build {
+        Ok(build) => build,
+        Err(e) => {
+            println!("> Error: {:?}", e);
+            return Response::fail(format!("Failed to build the site: {}", e));
+        }
+    };
+
+    match target.deploy(&site, &build) {
+        Ok(report) => {
+            println!("> Deployed {} files to {}", report.files, report.target);
+            let mut response = Response::success(String::from("Deployed site successfully! 🚀"));
+            response.body = Some(
+                serde_json::to_value(report).expect("Failed to serialize DeployReport in deploy_site"),
+            );
+            response
+        }
+        Err(e) => {
+            println!("> Error: {:?}", e);
+            Response::fail(format!("Failed to deploy site to {}: {}", target.name(), e))
+        }
+    }
+}
+
 /// Finds a site in the DB
 fn read_site(site_id: &str) -> Result<Option<SiteDetails>, String> {
     let site_repo = SiteRepository::new()
@@ -635,3 +1108,13 @@ fn read_site(site_id: &str) -> Result<Option<SiteDetails>, String> {
         .read(site_id)
         .map_err(|e| format!("Database error when reading site {}: {}", site_id, e))
 }
+
+/// Reads a site's settings from the DB, falling back to the defaults
+fn read_site_settings(site_id: &str) -> Result<SiteSettings, String> {
+    let site_repo = SiteRepository::new()
+        .map_err(|e| format!("Failed to initialize site repository: {}", e))?;
+
+    site_repo
+        .read_settings(site_id)
+        .map_err(|e| format!("Database error when reading settings for site {}: {}", site_id, e))
+}
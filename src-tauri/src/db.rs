@@ -1,5 +1,10 @@
 /// db.rs, responsible for building and interacting with the local sqlite3 database
-use rusqlite::{Connection, Result};
+use crate::{
+    assets::{image_extension_for, AssetStore},
+    driftwood::{legacy_filename, SiteSettings},
+    posts::{save_stats, unique_slug, FALLBACK_SLUG, STATS_COLUMNS},
+};
+use rusqlite::{params, Connection, Result};
 
 pub const DB_PATH: &str = "drift.db";
 
@@ -16,7 +21,8 @@ pub fn initialize_database() -> Result<()> {
       screenshot_url TEXT,
       password TEXT,
       required TEXT,
-      favicon TEXT
+      favicon TEXT,
+      settings TEXT
     )",
         [],
     )?;
@@ -30,6 +36,14 @@ pub fn initialize_database() -> Result<()> {
       date TEXT,
       content TEXT,
       excerpt TEXT,
+      tags TEXT,
+      slug TEXT,
+      wordpress_id TEXT,
+      word_count INTEGER,
+      reading_time INTEGER,
+      heading_count INTEGER,
+      image_count INTEGER,
+      link_count INTEGER,
       FOREIGN KEY(site_id) REFERENCES sites(id)
     )",
         [],
@@ -52,7 +66,225 @@ pub fn initialize_database() -> Result<()> {
         [],
     )?;
 
+    // slugs posts used before they were renamed, each becomes a redirect to the current slug
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS slug_history (
+      site_id TEXT NOT NULL,
+      post_id INTEGER NOT NULL,
+      slug TEXT NOT NULL COLLATE NOCASE,
+      PRIMARY KEY(site_id, slug),
+      FOREIGN KEY(post_id) REFERENCES posts(post_id)
+    )",
+        [],
+    )?;
+
+    // custom redirect and rewrite rules, written to the site's _redirects file
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS redirects (
+      id INTEGER PRIMARY KEY,
+      site_id TEXT NOT NULL,
+      from_path TEXT NOT NULL,
+      to_path TEXT NOT NULL,
+      status INTEGER NOT NULL DEFAULT 301,
+      force BOOLEAN DEFAULT FALSE,
+      FOREIGN KEY(site_id) REFERENCES sites(id)
+    )",
+        [],
+    )?;
+
+    // custom HTTP headers, written to the site's _headers file
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS headers (
+      id INTEGER PRIMARY KEY,
+      site_id TEXT NOT NULL,
+      path TEXT NOT NULL,
+      name TEXT NOT NULL,
+      value TEXT NOT NULL,
+      FOREIGN KEY(site_id) REFERENCES sites(id)
+    )",
+        [],
+    )?;
+
+    // columns added after the first release, older databases get them here
+    add_column_if_missing(&conn, "posts", "tags", "TEXT")?;
+    add_column_if_missing(&conn, "sites", "settings", "TEXT")?;
+    add_column_if_missing(&conn, "posts", "slug", "TEXT")?;
+    add_column_if_missing(&conn, "posts", "wordpress_id", "TEXT")?;
+    for column in STATS_COLUMNS {
+        add_column_if_missing(&conn, "posts", column, "INTEGER")?;
+    }
+
+    backfill_post_slugs(&conn)?;
+    backfill_post_stats(&conn)?;
+    move_header_images_to_assets(&conn)?;
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_posts_site_slug
+         ON posts(site_id, slug COLLATE NOCASE)",
+        [],
+    )?;
+    create_search_index(&conn)?;
+
     // rename_field()?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Creates the full-text index posts are searched with, and the triggers that keep it in step
+/// with the posts table. Posts saved before the index existed are indexed when it's created
+pub fn create_search_index(conn: &Connection) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'posts_fts')",
+        [],
+        |row| row.get(0),
+    )?;
+
+    // the index only holds the words, the text itself is read from posts
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS posts_fts USING fts5(
+      title, content, excerpt, tags,
+      content='posts', content_rowid='post_id',
+      tokenize='porter unicode61 remove_diacritics 2'
+    );
+
+    CREATE TRIGGER IF NOT EXISTS posts_fts_insert AFTER INSERT ON posts BEGIN
+      INSERT INTO posts_fts(rowid, title, content, excerpt, tags)
+      VALUES (new.post_id, new.title, new.content, new.excerpt, new.tags);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS posts_fts_delete AFTER DELETE ON posts BEGIN
+      INSERT INTO posts_fts(posts_fts, rowid, title, content, excerpt, tags)
+      VALUES ('delete', old.post_id, old.title, old.content, old.excerpt, old.tags);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS posts_fts_update
+    AFTER UPDATE OF title, content, excerpt, tags ON posts BEGIN
+      INSERT INTO posts_fts(posts_fts, rowid, title, content, excerpt, tags)
+      VALUES ('delete', old.post_id, old.title, old.content, old.excerpt, old.tags);
+      INSERT INTO posts_fts(rowid, title, content, excerpt, tags)
+      VALUES (new.post_id, new.title, new.content, new.excerpt, new.tags);
+    END;",
+    )?;
+
+    if !exists {
+        conn.execute("INSERT INTO posts_fts(posts_fts) VALUES ('rebuild')", [])?;
+    }
+    Ok(())
+}
+
+/// Adds a column to an existing table, skipping it if the table already has it.
+/// CREATE TABLE IF NOT EXISTS won't touch tables from older versions, so new columns go through here
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|name| name.ok())
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Posts saved before content statistics were counted get theirs here,
+/// with the Markdown extensions of the site they're on
+fn backfill_post_stats(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT posts.post_id, posts.content, sites.settings
+         FROM posts LEFT JOIN sites ON sites.id = posts.site_id
+         WHERE posts.word_count IS NULL",
+    )?;
+    let posts = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (post_id, content, settings) in posts {
+        let settings: SiteSettings = settings
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        save_stats(conn, post_id, &content, &settings.markdown)?;
+    }
+    Ok(())
+}
+
+/// Header images used to be stored in the database as raw bytes,
+/// those are written to the site's asset store and the post keeps the asset's URL
+fn move_header_images_to_assets(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "SELECT post_id, site_id, header_image FROM posts WHERE typeof(header_image) = 'blob'",
+    )?;
+    let images = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Vec<u8>>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (post_id, site_id, bytes) in images {
+        let extension = image_extension_for(&bytes).unwrap_or_default();
+        let stored = AssetStore::for_site_id(&site_id)
+            .and_then(|store| store.store_bytes(&bytes, extension));
+        match stored {
+            Ok(file_name) => {
+                conn.execute(
+                    "UPDATE posts SET header_image = ?1 WHERE post_id = ?2",
+                    params![AssetStore::url_for(&file_name), post_id],
+                )?;
+            }
+            // leave the bytes where they are so nothing is lost, the next start tries again
+            Err(e) => println!("> Failed to move header image of post {}: {}", post_id, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives posts saved before slugs existed the filename they were published under,
+/// so their URLs stay the same
+fn backfill_post_slugs(conn: &Connection) -> Result<()> {
+    let mut stmt =
+        conn.prepare("SELECT post_id, site_id, title FROM posts WHERE slug IS NULL OR slug = ''")?;
+    let posts = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (post_id, site_id, title) in posts {
+        let base = legacy_filename(&title).unwrap_or_default();
+        let base = if base.is_empty() {
+            FALLBACK_SLUG.to_string()
+        } else {
+            base
+        };
+        let slug = unique_slug(conn, &site_id, &base, post_id)?;
+        conn.execute(
+            "UPDATE posts SET slug = ?1 WHERE post_id = ?2",
+            params![slug, post_id],
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,776 @@
+/// Deploy targets, the places a built site can be published to
+use crate::builder::{content_type_for, copy_dir, BuildOutput};
+use crate::driftwood::{Git, OAuth2, SiteDetails};
+use crate::netlify::Netlify;
+use crate::s3::{etag_for, S3};
+use anyhow::{anyhow, Context, Result};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Written into exported folders, marks a directory as safe to clear on the next export
+const EXPORT_MARKER: &str = ".driftwood-export";
+
+/// DeployReport struct
+/// Summary of a deploy, sent back to the client
+/// target: Name of the target that was deployed to
+/// location: Where the site ended up (a folder, a deploy ID, ...)
+/// files: Number of files in the build
+/// uploaded: Number of files that had to be sent, unchanged files are skipped where the target allows it
+/// warnings: The build's warnings, passed along so the client can show them
+#[derive(Serialize, Debug)]
+pub struct DeployReport {
+    pub target: String,
+    pub location: String,
+    pub files: usize,
+    pub uploaded: usize,
+    pub warnings: Vec<String>,
+}
+
+/// Somewhere a built site can be published to
+pub trait DeployTarget {
+    /// Short name of the target, used in logs and reports
+    fn name(&self) -> String;
+
+    /// Publishes a built site
+    ///
+    /// # Arguments
+    ///
+    /// * `site` - The site being deployed
+    /// * `build` - The rendered site, from builder::build_site
+    fn deploy(&self, site: &SiteDetails, build: &BuildOutput) -> Result<DeployReport>;
+}
+
+/// DeployTargetConfig enum
+/// The JSON the client sends to pick a target, e.g. {"kind": "folder", "path": "/tmp/my-site"}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeployTargetConfig {
+    Netlify,
+    Folder {
+        path: String,
+    },
+    GitBranch {
+        remote: String,
+        #[serde(default = "default_git_branch")]
+        branch: String,
+        #[serde(default)]
+        orphan: bool,
+    },
+    /// Keys fall back to AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY from the .env file.
+    /// Old files are only cleaned up under a prefix, in a bucket marked `dedicated` to the site,
+    /// or in one that holds Driftwood's marker from such a deploy
+    S3 {
+        endpoint: String,
+        bucket: String,
+        #[serde(default = "default_s3_region")]
+        region: String,
+        #[serde(default)]
+        prefix: String,
+        access_key: Option<String>,
+        secret_key: Option<String>,
+        #[serde(default)]
+        dedicated: bool,
+    },
+}
+
+fn default_git_branch() -> String {
+    String::from("gh-pages")
+}
+
+fn default_s3_region() -> String {
+    String::from("us-east-1")
+}
+
+impl DeployTargetConfig {
+    /// Creates the target this config describes
+    pub fn into_target(self) -> Result<Box<dyn DeployTarget>> {
+        match self {
+            DeployTargetConfig::Netlify => Ok(Box::new(NetlifyTarget::new()?)),
+            DeployTargetConfig::Folder { path } => Ok(Box::new(FolderTarget::new(path))),
+            DeployTargetConfig::GitBranch {
+                remote,
+                branch,
+                orphan,
+            } => Ok(Box::new(GitBranchTarget::new(remote, branch, orphan))),
+            DeployTargetConfig::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+                access_key,
+                secret_key,
+                dedicated,
+            } => {
+                let access_key = match access_key {
+                    Some(key) => key,
+                    None => OAuth2::get_env_var("AWS_ACCESS_KEY_ID")
+                        .context("No access key given and AWS_ACCESS_KEY_ID isn't set")?,
+                };
+                let secret_key = match secret_key {
+                    Some(key) => key,
+                    None => OAuth2::get_env_var("AWS_SECRET_ACCESS_KEY")
+                        .context("No secret key given and AWS_SECRET_ACCESS_KEY isn't set")?,
+                };
+                let s3 = S3::new(&endpoint, &region, &bucket, &prefix, &access_key, &secret_key)?;
+                Ok(Box::new(S3Target::new(s3, dedicated)))
+            }
+        }
+    }
+}
+
+/// Deploys to the site's Netlify site, only uploading files Netlify doesn't have yet
+pub struct NetlifyTarget {
+    netlify: Netlify,
+}
+
+impl NetlifyTarget {
+    pub fn new() -> Result<NetlifyTarget> {
+        let netlify = Netlify::new().map_err(|e| anyhow!("{}", e))?;
+        Ok(NetlifyTarget { netlify })
+    }
+}
+
+impl DeployTarget for NetlifyTarget {
+    fn name(&self) -> String {
+        String::from("netlify")
+    }
+
+    fn deploy(&self, site: &SiteDetails, build: &BuildOutput) -> Result<DeployReport> {
+        let file_hashes = Netlify::generate_sha1_for_posts(&build.root)
+            .map_err(|e| anyhow!("Failed to hash the build: {}", e))?;
+
+        // site is the og site details, the response is the deploy details + site details
+        // the id will overlap
+        let deploy = self
+            .netlify
+            .send_file_checksums(site.clone(), &file_hashes)
+            .map_err(|e| anyhow!("Failed to create the deploy on Netlify: {}", e))?;
+        let deploy_id = deploy
+            .id
+            .ok_or_else(|| anyhow!("Netlify didn't return a deploy ID"))?;
+
+        let required = deploy.required.unwrap_or_default();
+        let uploaded = upload_required(&required, &file_hashes.files, |file_name| {
+            self.netlify
+                .upload_file(&build.root, &deploy_id, Path::new(file_name))
+                .map(|_| ())
+                .map_err(|e| anyhow!("{}", e))
+        })
+        .with_context(|| format!("Netlify deploy {} is incomplete", deploy_id))?;
+
+        Ok(DeployReport {
+            target: self.name(),
+            location: deploy_id,
+            files: build.files.len(),
+            uploaded,
+            warnings: build.warnings.clone(),
+        })
+    }
+}
+
+/// Uploads every file in the build with a hash Netlify asked for. A deploy with files missing
+/// would still go live, so any file that fails is an error, as is a hash no file has
+///
+/// # Arguments
+///
+/// * `required` - The hashes Netlify doesn't have yet
+/// * `files` - The build's files and their hashes
+/// * `upload` - Uploads one file, by its path in the build
+///
+/// # Returns
+///
+/// A Result containing the number of files uploaded
+fn upload_required(
+    required: &[String],
+    files: &HashMap<String, String>,
+    mut upload: impl FnMut(&str) -> Result<()>,
+) -> Result<usize> {
+    let mut uploaded = 0;
+    let mut failed = Vec::new();
+    for required_hash in required {
+        println!("> Required file: {:?}", required_hash);
+        let mut matched = false;
+        for (file_name, file_hash) in files {
+            if file_hash != required_hash {
+                continue;
+            }
+            matched = true;
+            println!("> Matching File hash: {} {}", file_name, file_hash);
+            match upload(file_name) {
+                Ok(()) => {
+                    println!("> File uploaded successfully.");
+                    uploaded += 1;
+                }
+                Err(e) => {
+                    println!("> Error: {:?}", e);
+                    failed.push(format!("{} ({})", file_name, e));
+                }
+            }
+        }
+        if !matched {
+            failed.push(format!(
+                "{} (no file in the build has this hash)",
+                required_hash
+            ));
+        }
+    }
+
+    if !failed.is_empty() {
+        return Err(anyhow!(
+            "{} files failed to upload: {}",
+            failed.len(),
+            failed.join(", ")
+        ));
+    }
+    Ok(uploaded)
+}
+
+/// Writes the built site to a folder on disk, for manual hosting, previews and archiving
+pub struct FolderTarget {
+    pub path: PathBuf,
+}
+
+impl FolderTarget {
+    pub fn new(path: impl Into<PathBuf>) -> FolderTarget {
+        FolderTarget { path: path.into() }
+    }
+
+    /// Empties the target folder, refusing to touch folders that didn't come from an export
+    fn clear(&self) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        if !self.path.is_dir() {
+            return Err(anyhow!("{} is not a folder", self.path.display()));
+        }
+
+        let is_empty = fs::read_dir(&self.path)?.next().is_none();
+        if !is_empty && !self.path.join(EXPORT_MARKER).exists() {
+            return Err(anyhow!(
+                "{} is not empty and wasn't created by Driftwood, pick an empty folder",
+                self.path.display()
+            ));
+        }
+
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DeployTarget for FolderTarget {
+    fn name(&self) -> String {
+        String::from("folder")
+    }
+
+    fn deploy(&self, _site: &SiteDetails, build: &BuildOutput) -> Result<DeployReport> {
+        println!("> Exporting site to {}", self.path.display());
+
+        self.clear()?;
+        copy_dir(&build.root, &self.path).context("Failed to copy the build")?;
+        fs::write(self.path.join(EXPORT_MARKER), build.files.join("\n"))
+            .context("Failed to write the export marker")?;
+
+        Ok(DeployReport {
+            target: self.name(),
+            location: self.path.to_string_lossy().to_string(),
+            files: build.files.len(),
+            uploaded: build.files.len(),
+            warnings: build.warnings.clone(),
+        })
+    }
+}
+
+/// Commits the built site to a branch of a git remote, for GitHub Pages style hosts
+/// remote: URL of the remote, anything git understands, including a local path
+/// branch: The branch the site is published on, gh-pages by default
+/// orphan: Replace the branch with a single fresh commit every deploy instead of adding to its history
+/// cache_dir: Bare repo the commits are made in, defaults to sites/{id}/deploy.git
+pub struct GitBranchTarget {
+    pub remote: String,
+    pub branch: String,
+    pub orphan: bool,
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl GitBranchTarget {
+    pub fn new(remote: String, branch: String, orphan: bool) -> GitBranchTarget {
+        GitBranchTarget {
+            remote,
+            branch,
+            orphan,
+            cache_dir: None,
+        }
+    }
+
+    fn open_cache(&self, site: &SiteDetails) -> Result<Repository> {
+        let cache_dir = match &self.cache_dir {
+            Some(dir) => dir.clone(),
+            None => site.build_site_path()?.join("deploy.git"),
+        };
+        let repo = match Repository::open_bare(&cache_dir) {
+            Ok(repo) => repo,
+            Err(_) => Repository::init_bare(&cache_dir)
+                .context("Failed to create the git deploy repository")?,
+        };
+        Ok(repo)
+    }
+}
+
+impl DeployTarget for GitBranchTarget {
+    fn name(&self) -> String {
+        String::from("git")
+    }
+
+    fn deploy(&self, site: &SiteDetails, build: &BuildOutput) -> Result<DeployReport> {
+        println!("> Deploying site to {} on {}", self.branch, self.remote);
+
+        let repo = self.open_cache(site)?;
+        let remote_tip = Git::fetch_branch(&repo, &self.remote, &self.branch)
+            .with_context(|| format!("Failed to fetch {} from {}", self.branch, self.remote))?;
+
+        // GitHub Pages runs Jekyll over the branch unless told not to,
+        // which hides files starting with an underscore. Added to the tree only,
+        // the build is shared with other targets
+        let tree_id = Git::write_tree_from_dir(&repo, &build.root)?;
+        let mut tree = repo.treebuilder(Some(&repo.find_tree(tree_id)?))?;
+        tree.insert(".nojekyll", repo.blob(b"")?, 0o100644)?;
+        let tree_id = tree.write()?;
+
+        // nothing to do if the branch already has exactly this build
+        if let Some(tip) = remote_tip {
+            if repo.find_commit(tip)?.tree_id() == tree_id {
+                println!("> Branch is already up to date");
+                return Ok(DeployReport {
+                    target: self.name(),
+                    location: tip.to_string(),
+                    files: build.files.len(),
+                    uploaded: 0,
+                    warnings: build.warnings.clone(),
+                });
+            }
+        }
+
+        let message = format!(
+            "Deploy {} ({} files)\n\nPublished by Driftwood on {}",
+            site.name.clone().unwrap_or_default(),
+            build.files.len(),
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+        );
+        let parent = if self.orphan { None } else { remote_tip };
+        let commit = Git::commit_tree_to_branch(&repo, &self.branch, tree_id, parent, &message)?;
+
+        // orphan commits don't descend from what's on the remote, so they need a force push
+        Git::push_branch(&repo, &self.remote, &self.branch, parent.is_none())
+            .with_context(|| format!("Failed to push {} to {}", self.branch, self.remote))?;
+
+        Ok(DeployReport {
+            target: self.name(),
+            location: commit.to_string(),
+            files: build.files.len(),
+            uploaded: build.files.len(),
+            warnings: build.warnings.clone(),
+        })
+    }
+}
+
+/// Uploads the built site to an S3-compatible bucket
+/// Objects whose ETag already matches the file's MD5 are skipped. Objects under the prefix that
+/// aren't part of the build are deleted, but only when there is a prefix, the bucket is
+/// dedicated to the site, or it holds Driftwood's marker, so a shared bucket is left alone
+/// dedicated: The whole bucket belongs to the site, set by the user
+pub struct S3Target {
+    s3: S3,
+    dedicated: bool,
+}
+
+impl S3Target {
+    pub fn new(s3: S3, dedicated: bool) -> S3Target {
+        S3Target { s3, dedicated }
+    }
+}
+
+impl DeployTarget for S3Target {
+    fn name(&self) -> String {
+        String::from("s3")
+    }
+
+    fn deploy(&self, _site: &SiteDetails, build: &BuildOutput) -> Result<DeployReport> {
+        println!("> Deploying site to bucket {}", self.s3.bucket);
+
+        let existing = self.s3.list_objects()?;
+        let mut wanted = HashSet::new();
+        let mut uploaded = 0;
+
+        for file in &build.files {
+            let key = self.s3.key_for(file);
+            let body = fs::read(build.path_of(file))?;
+            if existing.get(&key) != Some(&etag_for(&body)) {
+                self.s3.put_object(&key, body, content_type_for(file))?;
+                uploaded += 1;
+            }
+            wanted.insert(key);
+        }
+
+        // the marker says the bucket is Driftwood's to clean up, like it does for exported folders.
+        // It's only written where cleaning up is already allowed, a deploy into a shared bucket
+        // mustn't make the next one delete what else is in there
+        let marker = self.s3.key_for(EXPORT_MARKER);
+        let owned = !self.s3.prefix.is_empty() || self.dedicated || existing.contains_key(&marker);
+        if owned {
+            let marker_body = build.files.join("\n").into_bytes();
+            if existing.get(&marker) != Some(&etag_for(&marker_body)) {
+                self.s3.put_object(&marker, marker_body, "text/plain")?;
+            }
+            wanted.insert(marker);
+
+            // clear out posts and assets that were removed since the last deploy
+            for key in existing.keys().filter(|key| !wanted.contains(*key)) {
+                self.s3.delete_object(key)?;
+            }
+        } else {
+            println!("> Keeping old objects, the bucket has no prefix and no Driftwood marker");
+        }
+
+        Ok(DeployReport {
+            target: self.name(),
+            location: format!("s3://{}/{}", self.s3.bucket, self.s3.prefix),
+            files: build.files.len(),
+            uploaded,
+            warnings: build.warnings.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{build_site, SiteSource};
+    use crate::driftwood::Post;
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    fn create_test_site() -> SiteDetails {
+        SiteDetails {
+            name: Some("Test Site".to_string()),
+            domain: None,
+            id: Some("deploy-test".to_string()),
+            ssl: None,
+            url: None,
+            screenshot_url: None,
+            password: None,
+            required: None,
+            favicon: None,
+            favicon_path: None,
+        }
+    }
+
+    #[test]
+    fn test_build_and_export_to_folder() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let site = create_test_site();
+
+        let mut post = Post::new(String::from("Hello World"));
+        post.content = String::from("# Hello\n\nFirst post.");
+
+        let source = SiteSource {
+            posts: vec![post],
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, &dir.path().join("build"))?;
+        assert_eq!(
+            build.files,
+            vec![
+                "/_headers",
+                "/highlight.css",
+                "/index.html",
+                "/posts/hello-world.html",
+                "/search-index.json"
+            ]
+        );
+
+        let export = dir.path().join("export");
+        let target = FolderTarget::new(&export);
+        let report = target.deploy(&site, &build)?;
+        assert_eq!(report.files, 5);
+        assert!(export.join("index.html").exists());
+        assert!(fs::read_to_string(export.join("posts/hello-world.html"))?.contains("First post."));
+
+        // exporting again replaces the previous export
+        target.deploy(&site, &build)?;
+
+        // but folders Driftwood didn't create are left alone
+        let other = dir.path().join("other");
+        fs::create_dir(&other)?;
+        fs::write(other.join("notes.txt"), "keep me")?;
+        assert!(FolderTarget::new(&other).deploy(&site, &build).is_err());
+        assert!(other.join("notes.txt").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deploy_to_git_branch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let site = create_test_site();
+        let remote = Repository::init_bare(dir.path().join("remote.git"))?;
+        let remote_url = dir.path().join("remote.git").to_string_lossy().to_string();
+
+        let mut target = GitBranchTarget::new(remote_url, String::from("gh-pages"), false);
+        target.cache_dir = Some(dir.path().join("cache.git"));
+
+        let mut post = Post::new(String::from("Hello World"));
+        post.content = String::from("First post.");
+        let source = SiteSource {
+            posts: vec![post],
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, &dir.path().join("build"))?;
+        let first = target.deploy(&site, &build)?;
+
+        let tip = remote.refname_to_id("refs/heads/gh-pages")?;
+        assert_eq!(tip.to_string(), first.location);
+        let tree = remote.find_commit(tip)?.tree()?;
+        assert!(tree.get_name("index.html").is_some());
+        assert!(tree.get_name(".nojekyll").is_some());
+        assert!(!build.root.join(".nojekyll").exists());
+        assert!(tree.get_path(Path::new("posts/hello-world.html")).is_ok());
+
+        // an unchanged build doesn't make a new commit
+        let unchanged = target.deploy(&site, &build)?;
+        assert_eq!(unchanged.uploaded, 0);
+        assert_eq!(remote.refname_to_id("refs/heads/gh-pages")?, tip);
+
+        // a changed build is committed on top of the last deploy
+        let mut post = Post::new(String::from("Hello World"));
+        post.content = String::from("Edited post.");
+        let source = SiteSource {
+            posts: vec![post],
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, &dir.path().join("build"))?;
+        target.deploy(&site, &build)?;
+        let second = remote.find_commit(remote.refname_to_id("refs/heads/gh-pages")?)?;
+        assert_eq!(second.parent_id(0)?, tip);
+
+        // orphan deploys replace the history with a single commit
+        target.orphan = true;
+        let mut post = Post::new(String::from("Hello World"));
+        post.content = String::from("Edited again.");
+        let source = SiteSource {
+            posts: vec![post],
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, &dir.path().join("build"))?;
+        target.deploy(&site, &build)?;
+        let orphan = remote.find_commit(remote.refname_to_id("refs/heads/gh-pages")?)?;
+        assert_eq!(orphan.parent_count(), 0);
+
+        Ok(())
+    }
+
+    type FakeBucket = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Just enough of the S3 API to deploy against: list, put and delete
+    /// Returns the endpoint URL, the stored objects and the number of PUTs received
+    fn start_fake_s3() -> (String, FakeBucket, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let objects: FakeBucket = Arc::default();
+        let puts: Arc<Mutex<usize>> = Arc::default();
+
+        let (thread_objects, thread_puts) = (objects.clone(), puts.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                let mut signed = false;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap();
+                    match name.to_lowercase().as_str() {
+                        "content-length" => content_length = value.trim().parse().unwrap(),
+                        "authorization" => signed = value.contains("AWS4-HMAC-SHA256"),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap().to_string();
+                let target = parts.next().unwrap().to_string();
+                let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+                let key = path
+                    .trim_start_matches("/bucket")
+                    .trim_start_matches('/')
+                    .to_string();
+
+                let mut objects = thread_objects.lock().unwrap();
+                let (status, response_body) = if !signed {
+                    ("403 Forbidden", String::new())
+                } else if method == "GET" && query.contains("list-type=2") {
+                    let contents: String = objects
+                        .iter()
+                        .map(|(key, body)| {
+                            format!(
+                                "<Contents><Key>{}</Key><ETag>\"{}\"</ETag></Contents>",
+                                key,
+                                etag_for(body)
+                            )
+                        })
+                        .collect();
+                    (
+                        "200 OK",
+                        format!(
+                            "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                            contents
+                        ),
+                    )
+                } else if method == "PUT" {
+                    *thread_puts.lock().unwrap() += 1;
+                    objects.insert(key, body);
+                    ("200 OK", String::new())
+                } else if method == "DELETE" {
+                    objects.remove(&key);
+                    ("204 No Content", String::new())
+                } else {
+                    ("400 Bad Request", String::new())
+                };
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    response_body.len(),
+                    response_body
+                );
+            }
+        });
+
+        (endpoint, objects, puts)
+    }
+
+    #[test]
+    fn test_deploy_to_s3() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let site = create_test_site();
+        let (endpoint, objects, puts) = start_fake_s3();
+        let s3 = S3::new(&endpoint, "us-east-1", "bucket", "", "key", "secret")?;
+        let target = S3Target::new(s3, false);
+
+        // something left over from an older Driftwood deploy
+        {
+            let mut objects = objects.lock().unwrap();
+            objects.insert(String::from("posts/Old-Post.html"), b"old".to_vec());
+            objects.insert(String::from(EXPORT_MARKER), b"/posts/Old-Post.html".into());
+        }
+
+        let mut post = Post::new(String::from("Hello World"));
+        post.content = String::from("First post.");
+        let source = SiteSource {
+            posts: vec![post],
+            ..SiteSource::default()
+        };
+        let build = build_site(&site, source, &dir.path().join("build"))?;
+
+        let report = target.deploy(&site, &build)?;
+        assert_eq!(report.uploaded, 5);
+        {
+            let objects = objects.lock().unwrap();
+            assert!(objects.contains_key("index.html"));
+            assert!(objects.contains_key("posts/hello-world.html"));
+            assert!(!objects.contains_key("posts/Old-Post.html"));
+        }
+
+        // nothing changed, so nothing is uploaded again, the marker included
+        let report = target.deploy(&site, &build)?;
+        assert_eq!(report.uploaded, 0);
+        assert_eq!(*puts.lock().unwrap(), 6);
+
+        // a bucket without a prefix or a marker may hold other things, they're kept on every
+        // deploy since no marker is left behind to claim the bucket
+        let (endpoint, objects, _) = start_fake_s3();
+        let s3 = S3::new(&endpoint, "us-east-1", "bucket", "", "key", "secret")?;
+        objects
+            .lock()
+            .unwrap()
+            .insert(String::from("backups/db.sql"), b"keep".to_vec());
+        let shared = S3Target::new(s3, false);
+        shared.deploy(&site, &build)?;
+        shared.deploy(&site, &build)?;
+        {
+            let objects = objects.lock().unwrap();
+            assert!(objects.contains_key("backups/db.sql"));
+            assert!(objects.contains_key("index.html"));
+            assert!(!objects.contains_key(EXPORT_MARKER));
+        }
+
+        // unless the user says the bucket is the site's
+        let s3 = S3::new(&endpoint, "us-east-1", "bucket", "", "key", "secret")?;
+        S3Target::new(s3, true).deploy(&site, &build)?;
+        let objects = objects.lock().unwrap();
+        assert!(!objects.contains_key("backups/db.sql"));
+        assert!(objects.contains_key(EXPORT_MARKER));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_netlify_upload_failures_fail_the_deploy() -> Result<()> {
+        let files: HashMap<String, String> = [
+            (String::from("/index.html"), String::from("aaa")),
+            (String::from("/posts/hello.html"), String::from("bbb")),
+        ]
+        .into_iter()
+        .collect();
+
+        let uploaded = upload_required(&[String::from("aaa")], &files, |_| Ok(()))?;
+        assert_eq!(uploaded, 1);
+
+        let error = upload_required(
+            &[String::from("aaa"), String::from("bbb")],
+            &files,
+            |file| match file {
+                "/posts/hello.html" => Err(anyhow!("timed out")),
+                _ => Ok(()),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1 files failed to upload: /posts/hello.html (timed out)"
+        );
+
+        // Netlify asking for a file the build doesn't have means the deploy can't be finished
+        let error = upload_required(&[String::from("ccc")], &files, |_| Ok(())).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("ccc (no file in the build has this hash)"));
+        Ok(())
+    }
+}
@@ -1,19 +1,29 @@
+use crate::builder::DEFAULT_POSTS_PER_PAGE;
+use crate::highlight::{highlight_code_blocks, DEFAULT_HIGHLIGHT_THEME};
+use crate::math::latex_to_mathml;
+use crate::shortcodes::Shortcodes;
+use crate::stats::PostStats;
+use crate::themes::DEFAULT_THEME;
 use anyhow::{Context, Result};
 use git2::{Repository, Signature};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{HashMap, HashSet},
     env,
     error::Error,
     fs,
     io::Write,
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
-use tinytemplate::{format_unescaped, TinyTemplate};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Post {
     pub title: String,
+    /// The post's URL name, unique within its site. Empty until the post is saved
+    #[serde(default)]
+    pub slug: String,
     pub date: String,
     pub content: String,
     pub filename: String,
@@ -22,31 +32,9 @@ pub struct Post {
     pub post_id: u64,
     pub site_id: String,
     pub excerpt: String,
-}
-
-#[derive(Serialize)]
-struct BlogCardContext {
-    title: String,
-    date: String,
-    excerpt: String,
-    image: String,
-    sitename: String,
-    tags: String,
-    filename: String,
-}
-
-#[derive(Serialize)]
-struct IndexContext {
-    sitename: String,
-    blog_cards: String,
-}
-
-#[derive(Serialize)]
-struct PostContext {
-    title: String,
-    content: String,
-    date: String,
-    sitename: String,
+    /// Counted from the content when the post is saved, anything sent with it is ignored
+    #[serde(default)]
+    pub stats: PostStats,
 }
 
 /// SiteDetails struct
@@ -78,9 +66,154 @@ pub struct NewSite {
     pub rss_enabled: bool,
 }
 
-static POST_CARD_TEMPLATE: &'static str = include_str!("templates/default/blog-card-template.html");
-static POST_PAGE_TEMPLATE: &'static str = include_str!("templates/default/post-template.html");
-static INDEX_TEMPLATE: &'static str = include_str!("templates/default/index-template.html");
+/// The format dates are stored in on a Post, e.g. 2024/11/02 09:30 PM
+pub const POST_DATE_FORMAT: &str = "%Y/%m/%d %I:%M %p";
+
+/// Reads a date stored on a Post, also accepting RFC 3339 timestamps
+pub fn parse_post_date(date: &str) -> Option<chrono::NaiveDateTime> {
+    let date = date.trim();
+    chrono::NaiveDateTime::parse_from_str(date, POST_DATE_FORMAT)
+        .ok()
+        .or_else(|| {
+            chrono::DateTime::parse_from_rfc3339(date)
+                .ok()
+                .map(|date| date.naive_local())
+        })
+}
+
+/// Turns text into a lowercase, dash separated string that's safe in URLs,
+/// e.g. "What's new in v2.0?" -> "whats-new-in-v2-0".
+/// Letters from any script are kept, so "Café über Straße" -> "café-über-straße"
+pub fn slugify(text: &str) -> String {
+    use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+    text.nfc()
+        .flat_map(char::to_lowercase)
+        // apostrophes join words instead of splitting them
+        .filter(|c| !matches!(c, '\'' | '’'))
+        .map(|c| if c.is_alphanumeric() || is_combining_mark(c) { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Whether a slug can be used as is, words of letters and numbers joined by single dashes.
+/// Case isn't changed, so slugs from older versions like "Hello-World" stay valid
+pub fn is_valid_slug(slug: &str) -> bool {
+    use unicode_normalization::char::is_combining_mark;
+
+    !slug.is_empty()
+        && slug.split('-').all(|word| {
+            !word.is_empty() && word.chars().all(|c| c.is_alphanumeric() || is_combining_mark(c))
+        })
+}
+
+/// The filename older versions built from a title, ASCII letters and numbers joined by dashes.
+/// Posts saved before slugs existed keep it as their slug so their URLs don't change
+pub fn legacy_filename(title: &str) -> Result<String> {
+    let re = Regex::new(r"[^a-zA-Z0-9\s]")?;
+    // remove all special chars, replace with whitespace
+    let filename = re.replace_all(title, " ");
+    // remove all extra whitespace and join the words with a dash
+    Ok(filename.split_whitespace().collect::<Vec<_>>().join("-"))
+}
+
+/// SiteSettings struct
+/// Per-site options for how the site is built, stored as JSON on the site's row
+/// theme: Name of the theme under `themes/`, "default" is built in
+/// html_policy: What to do with raw HTML written in posts
+/// security_headers: Send the default security and caching headers along with the site's own
+/// markdown: Which Markdown extensions posts are written with
+/// highlight_theme: Colours for highlighted code blocks, empty turns highlighting off
+/// posts_per_page: Posts on each page of the index and tag archives, 0 puts them all on one page.
+///   New sites start at DEFAULT_POSTS_PER_PAGE, `SiteRepository::read_settings` reads settings
+///   saved before pagination as 0
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SiteSettings {
+    pub theme: String,
+    pub html_policy: HtmlPolicy,
+    pub security_headers: bool,
+    pub markdown: MarkdownOptions,
+    pub highlight_theme: String,
+    pub posts_per_page: usize,
+}
+
+impl Default for SiteSettings {
+    fn default() -> Self {
+        SiteSettings {
+            theme: DEFAULT_THEME.to_string(),
+            html_policy: HtmlPolicy::default(),
+            security_headers: true,
+            markdown: MarkdownOptions::default(),
+            highlight_theme: DEFAULT_HIGHLIGHT_THEME.to_string(),
+            posts_per_page: DEFAULT_POSTS_PER_PAGE,
+        }
+    }
+}
+
+/// MarkdownOptions struct
+/// The CommonMark extensions a site's posts are rendered with
+/// smart_punctuation: Curly quotes, en and em dashes and ellipses, off unless asked for
+/// heading_ids: Give every heading an anchor ID made from its text, `{#custom-id}` overrides it
+/// math: Render `$inline$` and `$$display$$` LaTeX as MathML, off so prices like $5 stay text
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct MarkdownOptions {
+    pub tables: bool,
+    pub footnotes: bool,
+    pub strikethrough: bool,
+    pub task_lists: bool,
+    pub smart_punctuation: bool,
+    pub heading_ids: bool,
+    pub math: bool,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        MarkdownOptions {
+            tables: true,
+            footnotes: true,
+            strikethrough: true,
+            task_lists: true,
+            smart_punctuation: false,
+            heading_ids: true,
+            math: false,
+        }
+    }
+}
+
+impl MarkdownOptions {
+    /// The pulldown-cmark options for these settings
+    pub fn parser_options(&self) -> pulldown_cmark::Options {
+        use pulldown_cmark::Options;
+
+        let mut options = Options::empty();
+        options.set(Options::ENABLE_TABLES, self.tables);
+        options.set(Options::ENABLE_FOOTNOTES, self.footnotes);
+        options.set(Options::ENABLE_STRIKETHROUGH, self.strikethrough);
+        options.set(Options::ENABLE_TASKLISTS, self.task_lists);
+        options.set(Options::ENABLE_SMART_PUNCTUATION, self.smart_punctuation);
+        options.set(Options::ENABLE_HEADING_ATTRIBUTES, self.heading_ids);
+        options.set(Options::ENABLE_MATH, self.math);
+        options
+    }
+}
+
+/// HtmlPolicy enum
+/// What happens to raw HTML embedded in a post's Markdown
+/// Allow: Kept as written, for sites where every author is trusted
+/// Strip: Removed along with javascript: links, only Markdown formatting is rendered
+/// Sanitize: Cleaned down to safe tags and attributes, scripts and event handlers are dropped
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HtmlPolicy {
+    #[default]
+    Allow,
+    Strip,
+    Sanitize,
+}
 
 impl Post {
     /// Creates a new Post instance with the given title.
@@ -98,17 +231,19 @@ impl Post {
         println!("Creating new post: {}", title);
 
         let date = chrono::Local::now();
-        let date = date.format("%Y/%m/%d %I:%M %p").to_string();
+        let date = date.format(POST_DATE_FORMAT).to_string();
         Post {
             title,
             date,
             content: String::new(),
+            slug: String::new(),
             filename: String::new(),
             tags: Vec::new(),
             image: None,
             post_id: 0,
             site_id: String::new(),
             excerpt: String::new(),
+            stats: PostStats::default(),
         }
     }
 
@@ -119,18 +254,7 @@ impl Post {
     /// A Result indicating success or failure.
     pub fn clean_filename(&mut self) -> Result<()> {
         println!("Cleaning filename: {}", self.title);
-        let re = Regex::new(r"[^a-zA-Z0-9\s]")?;
-        let mut new_filename = self.title.clone();
-        // remove all special chars, replace with whitespace
-        new_filename = re.replace_all(&new_filename, " ").to_string();
-        // remove all extra whitespace (leave one space between words)
-        new_filename = new_filename
-            .split_whitespace()
-            .collect::<Vec<_>>()
-            .join(" ");
-        // replace all whitespace with a dash
-        new_filename = new_filename.replace(" ", "-");
-        self.filename = new_filename;
+        self.filename = legacy_filename(&self.title)?;
         println!("Filename cleaned: {}", self.filename);
         Ok(())
     }
@@ -237,8 +361,11 @@ impl Post {
             "title:{}\ndate:{}\nexcerpt:{}\nimage:{}\ntags:{}\n{}",
             self.title,
             self.date,
-            "Write cool excerpt here",
-            "https://images.unsplash.com/photo-1615147342761-9238e15d8b96?ixid=MXwxMjA3fDB8MHxwaG90by1wYWdlfHx8fGVufDB8fHw%3D&ixlib=rb-1.2.1&auto=format&fit=crop&w=1001&q=80",
+            match self.excerpt.trim() {
+                "" => extract_excerpt(&self.content, &MarkdownOptions::default()),
+                excerpt => excerpt.to_string(),
+            },
+            self.image.as_deref().unwrap_or_default(),
             self.tags.join(","),
             post_text,
         );
@@ -268,11 +395,21 @@ impl Post {
 }
 
 impl SiteDetails {
+    /// The directory a site's files are kept in, by the site's ID
+    pub fn site_path_for(site_id: &str) -> PathBuf {
+        PathBuf::from(format!("sites/{}", site_id))
+    }
+
     pub fn build_site_path(&self) -> Result<PathBuf> {
-        let site_path = PathBuf::from(format!("sites/{}", self.id.clone().unwrap()));
+        let site_path = SiteDetails::site_path_for(self.id.as_deref().unwrap());
         Ok(site_path)
     }
 
+    /// Where the site is rendered to before it's deployed
+    pub fn build_output_path(&self) -> Result<PathBuf> {
+        Ok(self.build_site_path()?.join("build"))
+    }
+
     pub fn create_site_repo(&self) -> Result<()> {
         let repo_path = SiteDetails::build_site_path(self)?;
         let _repo = Repository::open(repo_path)?;
@@ -307,187 +444,451 @@ impl SiteDetails {
     }
 }
 
+/// Deepest heading level listed in a post's table of contents
+pub const TOC_MAX_LEVEL: u8 = 3;
+
+/// Longest automatic excerpt, in characters
+pub const EXCERPT_MAX_CHARS: usize = 280;
+
+/// RenderedPost struct
+/// A post's Markdown rendered for the build
+/// html: The post's body
+/// toc: Its headings, nested by level
+/// excerpt: Plain text summary for posts without an excerpt of their own, see extract_excerpt
+/// warnings: Problems rendering it that didn't stop the build, like unsupported math
+#[derive(Debug, Clone, Default)]
+pub struct RenderedPost {
+    pub html: String,
+    pub toc: Vec<TocEntry>,
+    pub excerpt: String,
+    pub warnings: Vec<String>,
+}
+
+/// TocEntry struct
+/// A heading in a post's table of contents, what templates see in `post.toc`
+/// level: 1 for `#`, 2 for `##` and so on
+/// id: The heading's anchor, link to it with `#id`
+/// children: Deeper headings that follow it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TocEntry {
+    pub level: u8,
+    pub id: String,
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
 /// Accepts a Post and converts it's markdown contents into HTML
-pub fn md_to_html(post: &Post, html_filename: &str) -> Result<()> {
+///
+/// # Arguments
+///
+/// * `post` - The post to render
+/// * `settings` - The site's settings, for its Markdown extensions and what to do with raw HTML
+/// * `images` - Image destinations to replace, from assets::import_local_images
+/// * `shortcodes` - The shortcodes the post can use
+///
+/// # Returns
+///
+/// The post's HTML, its table of contents, an automatic excerpt and any warnings
+pub fn md_to_html(
+    post: &Post,
+    settings: &SiteSettings,
+    images: &HashMap<String, String>,
+    shortcodes: &Shortcodes,
+) -> RenderedPost {
+    use pulldown_cmark::{Event, Parser, Tag};
+
     println!("Converting post {} to HTML.", post.title);
-    let parser = pulldown_cmark::Parser::new(&post.content);
-    println!("Parser created");
+    let options = settings.markdown;
+    // the sanitizer drops MathML and embeds, so generated HTML is held back until it has run
+    let mut fragments = Fragments::default();
+    // placeholders typed into the post would be swapped for generated HTML, e.g. inside an attribute
+    let source = Fragments::strip_markers(&post.content);
+    let (content, mut warnings) = shortcodes.expand(&source, options.parser_options(), |html| {
+        fragments.hold_block(html)
+    });
+    let mut events: Vec<Event> = Parser::new_ext(&content, options.parser_options())
+        .map(|event| match event {
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => Event::Start(Tag::Image {
+                link_type,
+                dest_url: images
+                    .get(dest_url.as_ref())
+                    .map(|url| url.clone().into())
+                    .unwrap_or(dest_url),
+                title,
+                id,
+            }),
+            event => event,
+        })
+        .collect();
+    let headings = collect_headings(&mut events, options.heading_ids);
+    // before stripping, which would take the <!-- more --> marker with it
+    let excerpt = excerpt_from_events(&events);
+
+    if settings.html_policy == HtmlPolicy::Strip {
+        events = events.into_iter().filter_map(strip_raw_html).collect();
+    }
+    // after stripping, highlighted blocks are HTML events themselves
+    if !settings.highlight_theme.is_empty() {
+        events = highlight_code_blocks(events);
+    }
+    if options.math {
+        events = events
+            .into_iter()
+            .map(|event| match event {
+                Event::InlineMath(latex) => fragments.hold(math_html(&latex, false, &mut warnings)),
+                Event::DisplayMath(latex) => fragments.hold(math_html(&latex, true, &mut warnings)),
+                event => event,
+            })
+            .collect();
+    }
+
     let mut html_output = String::new();
-    pulldown_cmark::html::push_html(&mut html_output, parser);
-    println!("HTML parsed");
-    fs::write(html_filename, &html_output)?;
-    println!("HTML wrote to disk");
-    Ok(())
+    pulldown_cmark::html::push_html(&mut html_output, events.into_iter());
+    if settings.html_policy == HtmlPolicy::Sanitize {
+        html_output = ammonia::Builder::default()
+            // keep heading anchors and classes themes style code with
+            .add_generic_attributes(["id", "class"])
+            .clean(&html_output)
+            .to_string();
+    }
+    RenderedPost {
+        html: fragments.restore(html_output),
+        toc: nest_toc(headings),
+        excerpt,
+        warnings,
+    }
 }
 
-pub fn read_and_parse(md_filename: &str, html_filename: &str) -> Result<bool, Box<dyn Error>> {
-    println!(">> Reading file: {}", md_filename);
-    let md_input = fs::read_to_string(md_filename)?;
-    println!(">> Building parser");
-    let parser = pulldown_cmark::Parser::new(&md_input);
-    println!(">> Creating HTML output string");
-    let mut html_output = String::new();
-    pulldown_cmark::html::push_html(&mut html_output, parser);
-    println!(">> Pushed HTML to cmark parser");
-    // output the new file to disk
-    fs::write(html_filename, &html_output)?;
-    println!(">> Wrote new file to disk");
-    Ok(true)
+/// Makes an excerpt from a post's Markdown: the text before a `<!-- more -->` marker, or the
+/// first paragraph when there isn't one, without markup and cut at a word boundary
+///
+/// # Arguments
+///
+/// * `markdown` - The post's content
+/// * `options` - The site's Markdown extensions
+pub fn extract_excerpt(markdown: &str, options: &MarkdownOptions) -> String {
+    let events: Vec<_> =
+        pulldown_cmark::Parser::new_ext(markdown, options.parser_options()).collect();
+    excerpt_from_events(&events)
 }
 
-pub fn template_html(
-    posts: Vec<String>,
-    site_path: &str,
-    site_name: &str,
-) -> Result<bool, Box<dyn Error>> {
-    println!(">> Templating HTML");
-
-    // create the templates
-    println!(">> Creating templates");
-    let mut tt_blog_card = TinyTemplate::new();
-    tt_blog_card.set_default_formatter(&format_unescaped);
-    let mut tt_post_page = TinyTemplate::new();
-    tt_post_page.set_default_formatter(&format_unescaped);
-    let mut tt_index = TinyTemplate::new();
-    tt_index.set_default_formatter(&format_unescaped);
-    println!(">> Adding templates");
-    tt_blog_card.add_template("card", POST_CARD_TEMPLATE)?;
-    tt_post_page.add_template("post", POST_PAGE_TEMPLATE)?;
-    tt_index.add_template("index", INDEX_TEMPLATE)?;
-    println!(">> Templates created");
-
-    let site_path = Path::new(&site_path);
-    let mut rendered_index = String::new();
-    let mut rendered_blog_cards = String::new();
-
-    println!(">> Iterating through posts");
-
-    // Create a vector to store post data
-    let mut post_data: Vec<(String, String, String, Vec<String>)> = Vec::new();
-
-    // First pass: extract date and other information
-    for post in &posts {
-        let post_file_path = Path::new(post);
-        let post_file = fs::read_to_string(post_file_path)?;
-        let mut date = String::new();
-        let mut tags = Vec::new();
-
-        for line in post_file.lines() {
-            println!(">> Line >> : {}", line.trim());
-            if line.trim().starts_with("<p>date:") {
-                date = line.replace("<p>date:", "").trim().to_string();
-            } else if line.trim().starts_with("tags:") {
-                tags = line
-                    .replace("tags:", "")
-                    .trim()
-                    .split(",")
-                    .map(|s| s.to_string())
-                    .collect::<Vec<String>>();
-                println!("Tags: {:?}", tags);
+fn excerpt_from_events(events: &[pulldown_cmark::Event]) -> String {
+    use pulldown_cmark::{Event, Tag, TagEnd};
+
+    let is_marker = |html: &str| {
+        let html: String = html.chars().filter(|c| !c.is_whitespace()).collect();
+        html.eq_ignore_ascii_case("<!--more-->")
+    };
+    let marker = events.iter().position(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => is_marker(html),
+        _ => false,
+    });
+
+    let mut text = String::new();
+    let mut in_paragraph = false;
+    for event in &events[..marker.unwrap_or(events.len())] {
+        match event {
+            Event::Start(Tag::Paragraph) => in_paragraph = true,
+            Event::End(TagEnd::Paragraph) if marker.is_none() && !text.trim().is_empty() => break,
+            Event::End(TagEnd::Paragraph) => {
+                in_paragraph = false;
+                text.push(' ');
+            }
+            // headings, code blocks and tables are left out
+            Event::Text(part) | Event::Code(part) if in_paragraph => {
+                text.push_str(&Fragments::strip(part));
             }
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => {}
         }
+    }
+    truncate_words(
+        &text.split_whitespace().collect::<Vec<_>>().join(" "),
+        EXCERPT_MAX_CHARS,
+    )
+}
 
-        post_data.push((post.clone(), date, post_file, tags));
+/// Shortens text to at most max_chars, ending on a whole word followed by an ellipsis
+fn truncate_words(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
     }
+    // room for the ellipsis
+    let cut: String = text.chars().take(max_chars - 1).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(space) => &cut[..space],
+        // one very long word
+        None => &cut,
+    };
+    format!(
+        "{}…",
+        cut.trim_end_matches(|c: char| c.is_ascii_punctuation() || c.is_whitespace())
+    )
+}
 
-    // Sort posts by date in descending order (newest first)
-    post_data.sort_by(|a, b| b.1.cmp(&a.1));
+/// HTML generated while rendering a post, swapped for a placeholder until the post's own HTML
+/// has been sanitized. Only HTML built from escaped input goes in here, each with whether it's
+/// a block that can't go inside a paragraph
+#[derive(Default)]
+struct Fragments(Vec<(String, bool)>);
 
-    // iterate through all the Posts
-    for (post, date, post_file, tags) in post_data {
-        println!("Tags: {:?}", tags);
-        println!(">> Post: {}", post);
-        let post_file_path = Path::new(&post);
-        let mut excerpt = String::new();
-        let mut image = String::new();
-        let mut new_post_file = String::new();
+impl Fragments {
+    /// Keeps the inline HTML and returns the text event standing in for it
+    fn hold<'a>(&mut self, html: String) -> pulldown_cmark::Event<'a> {
+        self.0.push((html, false));
+        pulldown_cmark::Event::Text(Self::placeholder(self.0.len() - 1).into())
+    }
 
-        // extract the date, excerpt, and image from the post_file
-        for line in post_file.lines() {
-            if line.trim().starts_with("<p>date:") {
-                continue;
-            } else if line.trim().starts_with("excerpt:") {
-                excerpt.push_str(line.replace("excerpt:", "").trim());
-                continue;
-            } else if line.trim().starts_with("image:") {
-                image.push_str(line.replace("image:", "").trim());
-                continue;
-            } else if line.trim().starts_with("tags:") {
+    /// Keeps the block HTML and returns the placeholder to put on its own line in the Markdown
+    fn hold_block(&mut self, html: String) -> String {
+        self.0.push((html, true));
+        Self::placeholder(self.0.len() - 1)
+    }
+
+    fn restore(&self, html: String) -> String {
+        static PARAGRAPH: OnceLock<Regex> = OnceLock::new();
+        let paragraph = PARAGRAPH.get_or_init(|| Regex::new(r"(?s)<p>(.*?)</p>").unwrap());
+        // blocks on consecutive lines are parsed as one paragraph, which they're taken out of
+        let mut html = paragraph
+            .replace_all(&html, |caps: &regex::Captures| self.split_paragraph(&caps[1]))
+            .to_string();
+
+        for (index, (fragment, _)) in self.0.iter().enumerate() {
+            let placeholder = Self::placeholder(index);
+            // a fragment on a line of its own replaces the paragraph Markdown put it in
+            let paragraph = format!("<p>{}</p>", placeholder);
+            html = match html.contains(&paragraph) {
+                true => html.replacen(&paragraph, fragment, 1),
+                false => html.replacen(&placeholder, fragment, 1),
+            };
+        }
+        html
+    }
+
+    /// A paragraph's text with each line that's a block placeholder moved out of it, and the
+    /// lines between them kept as paragraphs of their own
+    fn split_paragraph(&self, text: &str) -> String {
+        let is_block = |line: &str| {
+            line.strip_prefix('\u{E000}')
+                .and_then(|line| line.strip_suffix('\u{E001}'))
+                .and_then(|index| index.parse::<usize>().ok())
+                .and_then(|index| self.0.get(index))
+                .is_some_and(|(_, block)| *block)
+        };
+
+        let mut output = Vec::new();
+        let mut lines = Vec::new();
+        for line in text.split('\n') {
+            if !is_block(line) {
+                lines.push(line);
                 continue;
-            } else {
-                // put all the lines, except the above three, into new_post_file
-                new_post_file.push_str(line);
-                new_post_file.push_str("\n\r");
             }
+            if !lines.is_empty() {
+                output.push(format!("<p>{}</p>", lines.join("\n")));
+                lines.clear();
+            }
+            output.push(line.to_string());
+        }
+        if !lines.is_empty() {
+            output.push(format!("<p>{}</p>", lines.join("\n")));
         }
+        output.join("\n")
+    }
 
-        let _ = fs::write(post_file_path, &new_post_file);
-        // read in the updated file
-        let post_file = fs::read_to_string(post_file_path).unwrap();
-        println!(">> Post file written to disk");
-
-        let post_file_name = post_file_path
-            .file_name()
-            .unwrap()
-            .to_str()
-            .expect("Failed to convert post file path to string");
-
-        // also create a post title out of that
-        let post_title = post_file_name
-            .replace(".html", "")
-            .replace(".md", "")
-            .replace("-", " ");
-
-        // create the context/data for the template
-        println!(">> Creating blog card context");
-        let blog_card_context = BlogCardContext {
-            filename: format!("posts/{}", post_file_name),
-            title: post_title.clone(),
-            date: date.clone(),
-            excerpt: excerpt,
-            image: image,
-            sitename: site_name.to_string(),
-            tags: tags.join(", "),
-        };
-        println!("Blog card context: {}", blog_card_context.tags);
+    /// Private use characters, which neither the HTML writer nor the sanitizer escape
+    fn placeholder(index: usize) -> String {
+        format!("\u{E000}{}\u{E001}", index)
+    }
 
-        rendered_blog_cards.push_str(
-            &tt_blog_card
-                .render("card", &blog_card_context)
-                .expect("Failed templating the blog link context"),
-        );
-        println!(">> Blog card context templated");
+    /// Text with any placeholder characters taken out, for what the author wrote
+    fn strip_markers(text: &str) -> String {
+        text.replace(['\u{E000}', '\u{E001}'], "")
+    }
 
-        let post_context = PostContext {
-            title: post_title,
-            content: post_file,
-            date: date.clone(),
-            sitename: site_name.to_string(),
-        };
-        println!(">> Templating post: {}", post_file_path.to_str().unwrap());
-        let rendered_post = tt_post_page
-            .render("post", &post_context)
-            .expect("Failed templating the post context");
-        println!(">> Templated post: {}", post_file_path.to_str().unwrap());
-        fs::write(post_file_path, &rendered_post).expect("Failed to write post to disk");
-    }
-
-    println!(">> Templating index");
-    let index_context = IndexContext {
-        sitename: site_name.to_string(),
-        blog_cards: rendered_blog_cards,
-    };
+    /// Text with the placeholders taken out
+    fn strip(text: &str) -> String {
+        let mut stripped = String::with_capacity(text.len());
+        let mut held = false;
+        for c in text.chars() {
+            match c {
+                '\u{E000}' => held = true,
+                '\u{E001}' => held = false,
+                c if !held => stripped.push(c),
+                _ => {}
+            }
+        }
+        stripped
+    }
+}
+
+/// A formula as MathML, or as its source in a <code> when it uses LaTeX that isn't supported
+fn math_html(latex: &str, display: bool, warnings: &mut Vec<String>) -> String {
+    latex_to_mathml(latex, display).unwrap_or_else(|e| {
+        warnings.push(format!("Math not rendered, {}: {}", e, latex.trim()));
+        let delimiter = if display { "$$" } else { "$" };
+        format!(
+            "<code class=\"math-error\">{0}{1}{0}</code>",
+            delimiter,
+            latex
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+        )
+    })
+}
+
+/// Collects the headings a table of contents links to
+///
+/// # Arguments
+///
+/// * `events` - The post's Markdown events
+/// * `add_ids` - Give each heading without an explicit `{#id}` one made from its text,
+///   numbered when repeated. Headings without an ID are left out of the TOC
+fn collect_headings(events: &mut [pulldown_cmark::Event], add_ids: bool) -> Vec<TocEntry> {
+    use pulldown_cmark::{Event, Tag, TagEnd};
+
+    let mut used: HashSet<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Start(Tag::Heading { id: Some(id), .. }) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect();
+
+    let mut headings = Vec::new();
+    let mut start = None;
+    for index in 0..events.len() {
+        match &events[index] {
+            Event::Start(Tag::Heading { .. }) => start = Some(index),
+            Event::End(TagEnd::Heading(_)) => {
+                let Some(start) = start.take() else {
+                    continue;
+                };
+                let title: String = events[start + 1..index]
+                    .iter()
+                    .filter_map(|event| match event {
+                        Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                        _ => None,
+                    })
+                    .collect();
+                let Event::Start(Tag::Heading { level, id, .. }) = &mut events[start] else {
+                    continue;
+                };
+
+                if id.is_none() && add_ids {
+                    let base = match slugify(&title) {
+                        slug if slug.is_empty() => String::from("section"),
+                        slug => slug,
+                    };
+                    let mut unique = base.clone();
+                    let mut suffix = 1;
+                    while !used.insert(unique.clone()) {
+                        suffix += 1;
+                        unique = format!("{}-{}", base, suffix);
+                    }
+                    *id = Some(unique.into());
+                }
+
+                let level = *level as u8;
+                if let (Some(id), true) = (id, level <= TOC_MAX_LEVEL) {
+                    headings.push(TocEntry {
+                        level,
+                        id: id.to_string(),
+                        title: title.trim().to_string(),
+                        children: Vec::new(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// Nests each heading under the closest shallower heading before it
+fn nest_toc(headings: Vec<TocEntry>) -> Vec<TocEntry> {
+    let mut toc: Vec<TocEntry> = Vec::new();
+    for heading in headings {
+        let mut siblings = &mut toc;
+        while siblings.last().is_some_and(|last| last.level < heading.level) {
+            siblings = &mut siblings.last_mut().unwrap().children;
+        }
+        siblings.push(heading);
+    }
+    toc
+}
+
+/// Drops raw HTML from a Markdown event stream and defuses links to unsafe URLs
+fn strip_raw_html(event: pulldown_cmark::Event) -> Option<pulldown_cmark::Event> {
+    use pulldown_cmark::{Event, Tag};
+
+    match event {
+        Event::Html(_) | Event::InlineHtml(_) => None,
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Some(Event::Start(Tag::Link {
+            link_type,
+            dest_url: "#".into(),
+            title,
+            id,
+        })),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Some(Event::Start(Tag::Image {
+            link_type,
+            dest_url: "".into(),
+            title,
+            id,
+        })),
+        event => Some(event),
+    }
+}
 
-    rendered_index.push_str(
-        &tt_index
-            .render("index", &index_context)
-            .expect("Failed templating the index context"),
-    );
-    println!(">> Templated index");
-    println!(">> Writing index to disk");
-    let index_filename = site_path.join("index.html");
-    fs::write(index_filename, rendered_index)?;
+/// Whether a URL is fine to put in an href or src, relative URLs and http(s), mailto and tel are
+pub fn is_safe_url(url: &str) -> bool {
+    // browsers ignore whitespace and control characters inside the scheme, e.g. "java\tscript:"
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
 
+    match url.find(':') {
+        // a colon after a path, query or fragment starts is part of a relative URL
+        Some(colon) if !url[..colon].contains(['/', '?', '#']) => {
+            matches!(&url[..colon], "http" | "https" | "mailto" | "tel")
+        }
+        _ => true,
+    }
+}
+
+pub fn read_and_parse(
+    md_filename: &str,
+    html_filename: &str,
+    options: &MarkdownOptions,
+) -> Result<bool, Box<dyn Error>> {
+    println!(">> Reading file: {}", md_filename);
+    let md_input = fs::read_to_string(md_filename)?;
+    println!(">> Building parser");
+    let parser = pulldown_cmark::Parser::new_ext(&md_input, options.parser_options());
+    println!(">> Creating HTML output string");
+    let mut html_output = String::new();
+    pulldown_cmark::html::push_html(&mut html_output, parser);
+    println!(">> Pushed HTML to cmark parser");
+    // output the new file to disk
+    fs::write(html_filename, &html_output)?;
+    println!(">> Wrote new file to disk");
     Ok(true)
 }
 
@@ -523,6 +924,168 @@ impl Git {
         Ok(())
     }
 
+    /// Writes the contents of a directory into the repo as a tree, without needing a working tree
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository to write the blobs and trees into, can be bare
+    /// * `dir` - The directory to snapshot
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the Oid of the tree
+    pub fn write_tree_from_dir(repo: &Repository, dir: &Path) -> Result<git2::Oid, git2::Error> {
+        let to_git_error = |e: std::io::Error| git2::Error::from_str(&e.to_string());
+
+        let mut builder = repo.treebuilder(None)?;
+        for entry in fs::read_dir(dir).map_err(to_git_error)? {
+            let path = entry.map_err(to_git_error)?.path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if path.is_dir() {
+                let oid = Git::write_tree_from_dir(repo, &path)?;
+                builder.insert(name, oid, 0o040000)?;
+            } else {
+                let oid = repo.blob_path(&path)?;
+                builder.insert(name, oid, 0o100644)?;
+            }
+        }
+        builder.write()
+    }
+
+    /// Commits a tree to a branch, moving the branch to the new commit
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository to commit in
+    /// * `branch` - Name of the branch, e.g. gh-pages
+    /// * `tree_id` - The tree to commit, from write_tree_from_dir
+    /// * `parent` - The commit to build on, None makes an orphan commit
+    /// * `message` - The commit message
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the Oid of the new commit
+    pub fn commit_tree_to_branch(
+        repo: &Repository,
+        branch: &str,
+        tree_id: git2::Oid,
+        parent: Option<git2::Oid>,
+        message: &str,
+    ) -> Result<git2::Oid, git2::Error> {
+        let signature = Signature::now("Driftwood", "driftwood@example.com")?;
+        let tree = repo.find_tree(tree_id)?;
+        let parents = match parent {
+            Some(parent) => vec![repo.find_commit(parent)?],
+            None => vec![],
+        };
+        let parent_refs: Vec<&git2::Commit> = parents.iter().collect();
+
+        // commit without updating a ref, the branch may point somewhere unrelated (orphan deploys)
+        let oid = repo.commit(
+            None,
+            &signature,
+            &signature,
+            message,
+            &tree,
+            parent_refs.as_slice(),
+        )?;
+        repo.reference(&format!("refs/heads/{}", branch), oid, true, message)?;
+
+        Ok(oid)
+    }
+
+    /// Fetches a branch from a remote URL
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the Oid the branch points to on the remote, or None if it doesn't exist there yet
+    pub fn fetch_branch(
+        repo: &Repository,
+        remote_url: &str,
+        branch: &str,
+    ) -> Result<Option<git2::Oid>, git2::Error> {
+        let tracking_ref = format!("refs/remotes/deploy/{}", branch);
+
+        // drop what the last fetch saw, so a branch deleted on the remote isn't reused
+        if let Ok(mut reference) = repo.find_reference(&tracking_ref) {
+            reference.delete()?;
+        }
+
+        let mut remote = repo.remote_anonymous(remote_url)?;
+        let mut fetch_options = git2::FetchOptions::new();
+        fetch_options.remote_callbacks(Git::remote_callbacks());
+        remote.fetch(
+            &[format!("+refs/heads/{}:{}", branch, tracking_ref)],
+            Some(&mut fetch_options),
+            None,
+        )?;
+
+        // a branch that doesn't exist on the remote yet just doesn't get fetched
+        Ok(repo.refname_to_id(&tracking_ref).ok())
+    }
+
+    /// Pushes a local branch to the same branch on a remote URL
+    ///
+    /// # Arguments
+    ///
+    /// * `force` - Overwrite the remote branch, needed when the new commit isn't a descendant of it
+    pub fn push_branch(
+        repo: &Repository,
+        remote_url: &str,
+        branch: &str,
+        force: bool,
+    ) -> Result<(), git2::Error> {
+        let mut remote = repo.remote_anonymous(remote_url)?;
+        let refspec = format!(
+            "{}refs/heads/{}:refs/heads/{}",
+            if force { "+" } else { "" },
+            branch,
+            branch
+        );
+
+        // the remote reports rejected refs through this callback instead of an error
+        let rejection = std::cell::RefCell::new(None);
+        let mut callbacks = Git::remote_callbacks();
+        callbacks.push_update_reference(|refname, status| {
+            if let Some(status) = status {
+                *rejection.borrow_mut() = Some(format!("{} was rejected: {}", refname, status));
+            }
+            Ok(())
+        });
+
+        let mut push_options = git2::PushOptions::new();
+        push_options.remote_callbacks(callbacks);
+        remote.push(&[refspec], Some(&mut push_options))?;
+        drop(push_options);
+
+        match rejection.into_inner() {
+            Some(message) => Err(git2::Error::from_str(&message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Callbacks for talking to remotes, ssh uses the ssh agent and https uses git's credential helpers
+    fn remote_callbacks<'a>() -> git2::RemoteCallbacks<'a> {
+        let mut attempts = 0;
+        let mut callbacks = git2::RemoteCallbacks::new();
+        callbacks.credentials(move |url, username, allowed| {
+            // libgit2 keeps asking until it gets good credentials, don't loop forever
+            attempts += 1;
+            if attempts > 3 {
+                return Err(git2::Error::from_str("Authentication with the remote failed"));
+            }
+
+            if allowed.contains(git2::CredentialType::SSH_KEY) {
+                git2::Cred::ssh_key_from_agent(username.unwrap_or("git"))
+            } else if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+                git2::Cred::credential_helper(&git2::Config::open_default()?, url, username)
+            } else {
+                git2::Cred::default()
+            }
+        });
+        callbacks
+    }
+
     pub fn view_commit_history(repo_path: &str, limit: usize) -> Result<(), git2::Error> {
         let repo = Repository::open(repo_path)?;
         let mut revwalk = repo.revwalk()?;
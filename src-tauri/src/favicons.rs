@@ -0,0 +1,136 @@
+/// Favicons, every icon a site needs is generated from the one image saved with the site
+use anyhow::{Context, Result};
+use image::{
+    codecs::ico::{IcoEncoder, IcoFrame},
+    imageops::FilterType,
+    DynamicImage, ExtendedColorType, ImageFormat,
+};
+use serde::Serialize;
+use std::{fs, io::Cursor, path::Path};
+
+/// Sizes packed into favicon.ico
+const ICO_SIZES: [u32; 3] = [16, 32, 48];
+
+/// The PNG icons written next to favicon.ico, as (file name, size, rel)
+const PNG_ICONS: [(&str, u32, &str); 5] = [
+    ("favicon-16x16.png", 16, "icon"),
+    ("favicon-32x32.png", 32, "icon"),
+    ("android-chrome-192x192.png", 192, "icon"),
+    ("android-chrome-512x512.png", 512, "icon"),
+    ("apple-touch-icon.png", 180, "apple-touch-icon"),
+];
+
+/// FaviconLink struct
+/// A <link> tag for an icon, what templates see in `site.favicons`
+/// href: Path relative to the site root, prefix it with `root` in templates
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FaviconLink {
+    pub rel: String,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+    pub sizes: String,
+    pub href: String,
+}
+
+/// Writes favicon.ico and the PNG icons into the build
+///
+/// # Arguments
+///
+/// * `source` - The site's favicon, any image the image crate can read, .ico included
+/// * `out_dir` - The build's root directory
+///
+/// # Returns
+///
+/// The link tags for the written icons
+pub fn write_favicons(source: &[u8], out_dir: &Path) -> Result<Vec<FaviconLink>> {
+    let image = image::load_from_memory(source).context("The site's favicon isn't an image")?;
+    // icons are square, pad anything else instead of stretching it
+    let image = square(image);
+
+    let mut frames = Vec::new();
+    for size in ICO_SIZES {
+        let icon = resize(&image, size);
+        frames.push(IcoFrame::as_png(
+            icon.as_raw(),
+            size,
+            size,
+            ExtendedColorType::Rgba8,
+        )?);
+    }
+    let mut ico = Vec::new();
+    IcoEncoder::new(&mut ico).encode_images(&frames)?;
+    fs::write(out_dir.join("favicon.ico"), ico).context("Failed to write favicon.ico")?;
+
+    let mut links = vec![FaviconLink {
+        rel: String::from("icon"),
+        mime_type: String::from("image/x-icon"),
+        sizes: ICO_SIZES.map(|size| format!("{0}x{0}", size)).join(" "),
+        href: String::from("favicon.ico"),
+    }];
+    for (file_name, size, rel) in PNG_ICONS {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(resize(&image, size)).write_to(&mut png, ImageFormat::Png)?;
+        fs::write(out_dir.join(file_name), png.into_inner())
+            .with_context(|| format!("Failed to write {}", file_name))?;
+        links.push(FaviconLink {
+            rel: rel.to_string(),
+            mime_type: String::from("image/png"),
+            sizes: format!("{0}x{0}", size),
+            href: file_name.to_string(),
+        });
+    }
+
+    Ok(links)
+}
+
+fn square(image: DynamicImage) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    if width == height {
+        return image;
+    }
+    let side = width.max(height);
+    let mut canvas = image::RgbaImage::new(side, side);
+    image::imageops::overlay(
+        &mut canvas,
+        &image.to_rgba8(),
+        ((side - width) / 2) as i64,
+        ((side - height) / 2) as i64,
+    );
+    DynamicImage::ImageRgba8(canvas)
+}
+
+fn resize(image: &DynamicImage, size: u32) -> image::RgbaImage {
+    image
+        .resize_exact(size, size, FilterType::Lanczos3)
+        .to_rgba8()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_write_favicons() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut source = Cursor::new(Vec::new());
+        RgbaImage::from_pixel(300, 200, Rgba([74, 144, 226, 255]))
+            .write_to(&mut source, ImageFormat::Png)?;
+
+        let links = write_favicons(source.get_ref(), dir.path())?;
+        assert_eq!(links.len(), 6);
+        assert_eq!(links[0].sizes, "16x16 32x32 48x48");
+        assert_eq!(links.last().unwrap().rel, "apple-touch-icon");
+
+        let touch = image::open(dir.path().join("apple-touch-icon.png"))?;
+        assert_eq!((touch.width(), touch.height()), (180, 180));
+        // the ico can be read back and used as a source itself
+        let ico = fs::read(dir.path().join("favicon.ico"))?;
+        assert_eq!(image::guess_format(&ico)?, ImageFormat::Ico);
+        let again = tempfile::tempdir()?;
+        assert_eq!(write_favicons(&ico, again.path())?, links);
+
+        assert!(write_favicons(b"not an image", dir.path()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+/// Custom HTTP headers for a site, written to a Netlify `_headers` file when it's built
+use crate::db::DB_PATH;
+use rusqlite::{params, Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Headers every site gets unless it turns them off in its settings
+const DEFAULT_HEADERS: [(&str, &str, &str); 5] = [
+    ("/*", "X-Content-Type-Options", "nosniff"),
+    ("/*", "X-Frame-Options", "SAMEORIGIN"),
+    ("/*", "Referrer-Policy", "strict-origin-when-cross-origin"),
+    ("/*", "Permissions-Policy", "camera=(), microphone=(), geolocation=()"),
+    // asset names are content hashes, a changed file gets a new URL
+    ("/assets/*", "Cache-Control", "public, max-age=31536000, immutable"),
+];
+
+/// HeaderRule struct
+/// A header sent with every file matching a path
+/// id: Row ID for custom rules, 0 for the defaults
+/// path: The path or pattern it applies to, e.g. /* or /posts/*
+/// name: The header's name, e.g. Content-Security-Policy
+/// value: The header's value
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HeaderRule {
+    #[serde(default)]
+    pub id: u64,
+    pub path: String,
+    pub name: String,
+    pub value: String,
+}
+
+impl HeaderRule {
+    pub fn new(path: &str, name: &str, value: &str) -> HeaderRule {
+        HeaderRule {
+            id: 0,
+            path: path.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// Checks the rule can be written to `_headers` without breaking the file
+    ///
+    /// # Returns
+    ///
+    /// A Result with a message describing what's wrong with the rule
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.path.starts_with('/') || self.path.chars().any(char::is_whitespace) {
+            return Err(format!(
+                "'{}' must be a path starting with / and without spaces",
+                self.path
+            ));
+        }
+
+        // header names are HTTP tokens, anything else would be read as a different line
+        let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+        if self.name.is_empty() || !self.name.chars().all(is_token_char) {
+            return Err(format!("'{}' is not a valid header name", self.name));
+        }
+
+        if self.value.trim().is_empty() {
+            return Err(format!("{} needs a value", self.name));
+        }
+        if self.value.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(format!("The value for {} can't contain line breaks", self.name));
+        }
+
+        if self.name.eq_ignore_ascii_case("Content-Security-Policy") {
+            validate_csp(&self.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Catches the CSP mistakes that silently block a whole site, like a missing semicolon
+fn validate_csp(policy: &str) -> Result<(), String> {
+    for directive in policy.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+        let name = directive.split_whitespace().next().unwrap_or_default();
+        let is_directive_name = name.chars().all(|c| c.is_ascii_lowercase() || c == '-');
+        if !is_directive_name {
+            return Err(format!("'{}' is not a Content-Security-Policy directive", name));
+        }
+        // "default-src 'self' script-src ..." is one directive, not two
+        if directive
+            .split_whitespace()
+            .skip(1)
+            .any(|source| source.ends_with("-src") || source.ends_with("-src-elem"))
+        {
+            return Err(format!("Missing ';' in Content-Security-Policy near '{}'", directive));
+        }
+    }
+    Ok(())
+}
+
+/// The site's rules with the default security headers applied first,
+/// a custom rule for the same path and header replaces the default
+///
+/// # Arguments
+///
+/// * `custom` - The site's own rules
+/// * `include_defaults` - Whether the site uses the default headers
+pub fn effective_headers(custom: &[HeaderRule], include_defaults: bool) -> Vec<HeaderRule> {
+    let mut rules = Vec::new();
+    if include_defaults {
+        for (path, name, value) in DEFAULT_HEADERS {
+            let overridden = custom
+                .iter()
+                .any(|rule| rule.path == path && rule.name.eq_ignore_ascii_case(name));
+            if !overridden {
+                rules.push(HeaderRule::new(path, name, value));
+            }
+        }
+    }
+    rules.extend(custom.iter().cloned());
+    rules
+}
+
+/// Builds the contents of a `_headers` file, grouping the rules by path
+pub fn headers_file(rules: &[HeaderRule]) -> String {
+    let mut paths: Vec<&str> = Vec::new();
+    for rule in rules {
+        if !paths.contains(&rule.path.as_str()) {
+            paths.push(&rule.path);
+        }
+    }
+
+    let mut file = String::from("# Generated by Driftwood, edit headers in the app\n");
+    for path in paths {
+        file.push_str(path);
+        file.push('\n');
+        for rule in rules.iter().filter(|rule| rule.path == path) {
+            file.push_str(&format!("  {}: {}\n", rule.name, rule.value.trim()));
+        }
+    }
+    file
+}
+
+pub struct HeaderRepository {
+    conn: Connection,
+}
+
+impl HeaderRepository {
+    pub fn new() -> Result<Self> {
+        let conn = Connection::open(Path::new(DB_PATH))?;
+        Ok(Self { conn })
+    }
+
+    /// Adds a custom header rule
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new rule's ID
+    pub fn create(&self, site_id: &str, rule: &HeaderRule) -> Result<u64> {
+        self.conn.execute(
+            "INSERT INTO headers (site_id, path, name, value) VALUES (?1, ?2, ?3, ?4)",
+            params![site_id, rule.path, rule.name, rule.value],
+        )?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    pub fn delete(&self, site_id: &str, id: u64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM headers WHERE site_id = ?1 AND id = ?2",
+            params![site_id, id],
+        )?;
+        Ok(())
+    }
+
+    /// The site's custom rules, in the order they were added
+    pub fn list(&self, site_id: &str) -> Result<Vec<HeaderRule>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, path, name, value FROM headers WHERE site_id = ?1 ORDER BY id",
+        )?;
+
+        let rules = stmt.query_map(params![site_id], |row| {
+            Ok(HeaderRule {
+                id: row.get(0)?,
+                path: row.get(1)?,
+                name: row.get(2)?,
+                value: row.get(3)?,
+            })
+        })?;
+
+        rules.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers_file_and_validation() {
+        let csp = HeaderRule::new(
+            "/*",
+            "Content-Security-Policy",
+            "default-src 'self'; img-src 'self' https:",
+        );
+        let frames = HeaderRule::new("/*", "x-frame-options", "DENY");
+        assert!(csp.validate().is_ok());
+        assert!(frames.validate().is_ok());
+
+        let invalid = [
+            HeaderRule::new("posts/*", "Cache-Control", "no-cache"),
+            HeaderRule::new("/*", "Bad Header", "value"),
+            HeaderRule::new("/*", "X-Injected", "value\n/other"),
+            HeaderRule::new("/*", "Cache-Control", " "),
+            HeaderRule::new("/*", "Content-Security-Policy", "default-src 'self' script-src 'none'"),
+        ];
+        for rule in invalid {
+            assert!(rule.validate().is_err(), "{:?} should be invalid", rule);
+        }
+
+        let rules = effective_headers(&[csp, frames], true);
+        let file = headers_file(&rules);
+        // the custom X-Frame-Options replaces the default one
+        assert!(!file.contains("SAMEORIGIN"));
+        assert!(file.starts_with(
+            "# Generated by Driftwood, edit headers in the app\n\
+             /*\n  X-Content-Type-Options: nosniff\n"
+        ));
+        assert!(file.contains("  x-frame-options: DENY\n"));
+        assert!(file.contains("/assets/*\n  Cache-Control: public, max-age=31536000, immutable\n"));
+        // one block per path
+        assert_eq!(file.lines().filter(|line| *line == "/*").count(), 1);
+
+        assert!(effective_headers(&[], false).is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+/// Syntax highlighting for fenced code blocks, done while building so pages don't need JavaScript
+use anyhow::{anyhow, Result};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag, TagEnd};
+use std::sync::OnceLock;
+use syntect::{
+    highlighting::ThemeSet,
+    html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+/// Highlight theme new sites start with
+pub const DEFAULT_HIGHLIGHT_THEME: &str = "InspiredGitHub";
+
+/// Stylesheet with the highlight theme's colours, written to the root of the build
+pub const HIGHLIGHT_STYLESHEET: &str = "highlight.css";
+
+/// Classes are prefixed so they can't clash with a theme's own
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// The highlight themes a site can pick, sorted by name
+pub fn list_highlight_themes() -> Vec<String> {
+    let mut names: Vec<String> = themes().themes.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+/// The stylesheet for a highlight theme
+///
+/// # Arguments
+///
+/// * `name` - One of list_highlight_themes()
+pub fn theme_css(name: &str) -> Result<String> {
+    let theme = themes()
+        .themes
+        .get(name)
+        .ok_or_else(|| anyhow!("No highlight theme named '{}'", name))?;
+    Ok(css_for_theme_with_class_style(theme, CLASS_STYLE)?)
+}
+
+/// Replaces fenced code blocks that name a known language with highlighted HTML.
+/// Blocks without a language, or with one syntect doesn't know, are left as plain code
+pub fn highlight_code_blocks(events: Vec<Event>) -> Vec<Event> {
+    let mut output = Vec::with_capacity(events.len());
+    let mut block: Option<(String, String)> = None;
+
+    for event in events {
+        match (&mut block, event) {
+            (None, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))))
+                if language_of(&info).is_some_and(|lang| find_syntax(lang).is_some()) =>
+            {
+                block = Some((language_of(&info).unwrap().to_string(), String::new()));
+            }
+            (Some((_, code)), Event::Text(text)) => code.push_str(&text),
+            (Some(_), Event::End(TagEnd::CodeBlock)) => {
+                let (lang, code) = block.take().unwrap();
+                output.push(Event::Html(CowStr::from(highlight(&code, &lang))));
+            }
+            (_, event) => output.push(event),
+        }
+    }
+    output
+}
+
+/// The language in an info string like "rust,ignore" or "js title=app.js"
+fn language_of(info: &str) -> Option<&str> {
+    info.split([',', ' ', '\t'])
+        .next()
+        .filter(|lang| !lang.is_empty())
+}
+
+fn find_syntax(lang: &str) -> Option<&'static syntect::parsing::SyntaxReference> {
+    let syntaxes = syntaxes();
+    syntaxes
+        .find_syntax_by_token(lang)
+        .or_else(|| syntaxes.find_syntax_by_token(&lang.to_lowercase()))
+}
+
+fn highlight(code: &str, lang: &str) -> String {
+    let syntax = find_syntax(lang).unwrap_or_else(|| syntaxes().find_syntax_plain_text());
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes(), CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        // syntect only fails on broken grammars, the line is still escaped when it does
+        if generator
+            .parse_html_for_line_which_includes_newline(line)
+            .is_err()
+        {
+            return format!(
+                "<pre><code class=\"language-{}\">{}</code></pre>\n",
+                escape(lang),
+                escape(code)
+            );
+        }
+    }
+    format!(
+        "<pre class=\"hl-code\"><code class=\"language-{}\">{}</code></pre>\n",
+        escape(lang),
+        generator.finalize()
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    #[test]
+    fn test_highlight_code_blocks() -> Result<()> {
+        let markdown = "```rust\nfn main() { let x = \"<b>\"; }\n```\n\n```\nplain\n```\n\n```nosuchlang\nkept\n```\n";
+        let mut output = String::new();
+        html::push_html(
+            &mut output,
+            highlight_code_blocks(Parser::new(markdown).collect()).into_iter(),
+        );
+
+        assert!(output.contains(r#"<pre class="hl-code"><code class="language-rust">"#));
+        assert!(
+            output.contains(r#"<span class="hl-storage hl-type hl-function hl-rust">fn</span>"#)
+        );
+        assert!(output.contains("&lt;b&gt;") && !output.contains("<b>"));
+        assert!(output.contains("<pre><code>plain\n</code></pre>"));
+        assert!(output.contains(r#"<pre><code class="language-nosuchlang">kept"#));
+
+        assert!(theme_css(DEFAULT_HIGHLIGHT_THEME)?.contains(".hl-code"));
+        assert!(theme_css("Nope").is_err());
+        assert!(list_highlight_themes().contains(&DEFAULT_HIGHLIGHT_THEME.to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+/// Responsive images, the build resizes each PNG, JPEG and WebP image in a site's assets so pages
+/// can offer browsers a srcset to pick from. PNGs and JPEGs also get WebP copies of each size,
+/// kept where they come out smaller, which with the lossless encoder is mostly flat graphics
+/// rather than photos
+use crate::driftwood::SiteDetails;
+use anyhow::{Context, Result};
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// Widths generated for every image, those wider than the original are skipped
+pub const RESPONSIVE_WIDTHS: [u32; 3] = [480, 960, 1600];
+
+/// `sizes` used for images in post bodies, matches the width of the post column
+pub const CONTENT_SIZES: &str = "(max-width: 800px) 100vw, 800px";
+
+/// Where resized copies go in the build, next to the originals
+const SIZES_DIR: &str = "sizes";
+
+/// Name of the file in each cache entry describing what was generated
+const META_FILE: &str = "meta.json";
+
+/// ImageSource struct
+/// One entry of a srcset
+/// url: Site-absolute URL, e.g. /assets/sizes/3f78-480.png
+/// width: Width of the image in pixels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageSource {
+    pub url: String,
+    pub width: u32,
+}
+
+/// ImageSet struct
+/// The versions of an image the build produced, what templates see as `post.image_set`
+/// width, height: Size of the original
+/// sources: Resized copies in the original format, smallest first, ending with the original
+/// webp: WebP copies of the widths where WebP made the image smaller, smallest first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageSet {
+    pub width: u32,
+    pub height: u32,
+    pub sources: Vec<ImageSource>,
+    pub webp: Vec<ImageSource>,
+}
+
+/// CachedImage struct
+/// meta.json of a cache entry, URLs are rebuilt from the source's name on each build
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedImage {
+    width: u32,
+    height: u32,
+    widths: Vec<u32>,
+    webp_widths: Vec<u32>,
+}
+
+/// ImagePipeline struct
+/// Generated files are cached by the SHA1 of their source, so rebuilds only resize new images
+pub struct ImagePipeline {
+    pub cache_dir: PathBuf,
+}
+
+impl ImagePipeline {
+    /// The pipeline for a site, caching into `sites/{id}/image_cache`
+    pub fn for_site(site: &SiteDetails) -> Result<ImagePipeline> {
+        Ok(ImagePipeline::new(
+            site.build_site_path()?.join("image_cache"),
+        ))
+    }
+
+    pub fn new(cache_dir: PathBuf) -> ImagePipeline {
+        ImagePipeline { cache_dir }
+    }
+
+    /// Generates the responsive versions of every image in an assets directory
+    ///
+    /// # Arguments
+    ///
+    /// * `assets_dir` - The site's asset store
+    /// * `out_dir` - The build, copies are written to its `assets/sizes` directory
+    ///
+    /// # Returns
+    ///
+    /// The generated sets by the original's URL, e.g. `/assets/3f78.png`
+    pub fn process_dir(
+        &self,
+        assets_dir: &Path,
+        out_dir: &Path,
+    ) -> Result<HashMap<String, ImageSet>> {
+        let mut images = HashMap::new();
+        if !assets_dir.is_dir() {
+            return Ok(images);
+        }
+
+        for entry in fs::read_dir(assets_dir)? {
+            let path = entry?.path();
+            let Some(format) = resizable_format(&path) else {
+                continue;
+            };
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            // one broken image shouldn't stop the whole site from building
+            match self.process(&path, format, out_dir) {
+                Ok(set) => {
+                    images.insert(format!("/assets/{}", file_name), set);
+                }
+                Err(e) => println!(">> Skipping responsive versions of {}: {}", file_name, e),
+            }
+        }
+        Ok(images)
+    }
+
+    /// Writes the resized copies of one image into the build, from the cache when it has them
+    fn process(&self, source: &Path, format: ImageFormat, out_dir: &Path) -> Result<ImageSet> {
+        let bytes = fs::read(source)?;
+        let hash = sha1_smol::Sha1::from(&bytes).digest().to_string();
+        let extension = format.extensions_str()[0];
+        let entry_dir = self.cache_dir.join(&hash);
+
+        let cached = match read_cache_entry(&entry_dir) {
+            Some(cached) => cached,
+            None => generate(&bytes, format, extension, &entry_dir)
+                .with_context(|| format!("Failed to resize {}", source.display()))?,
+        };
+
+        let sizes_dir = out_dir.join("assets").join(SIZES_DIR);
+        fs::create_dir_all(&sizes_dir)?;
+        let original_url = format!("/assets/{}", source.file_name().unwrap().to_string_lossy());
+
+        let mut set = ImageSet {
+            width: cached.width,
+            height: cached.height,
+            sources: Vec::new(),
+            webp: Vec::new(),
+        };
+        for &width in &cached.widths {
+            set.sources
+                .push(copy_size(&entry_dir, &sizes_dir, &hash, width, extension)?);
+        }
+        set.sources.push(ImageSource {
+            url: original_url,
+            width: cached.width,
+        });
+        for &width in &cached.webp_widths {
+            set.webp
+                .push(copy_size(&entry_dir, &sizes_dir, &hash, width, "webp")?);
+        }
+        Ok(set)
+    }
+}
+
+/// Copies one generated size from the cache into the build's sizes directory
+fn copy_size(
+    entry_dir: &Path,
+    sizes_dir: &Path,
+    hash: &str,
+    width: u32,
+    extension: &str,
+) -> Result<ImageSource> {
+    let name = format!("{}-{}.{}", hash, width, extension);
+    fs::copy(
+        entry_dir.join(format!("{}.{}", width, extension)),
+        sizes_dir.join(&name),
+    )?;
+    Ok(ImageSource {
+        url: format!("/assets/{}/{}", SIZES_DIR, name),
+        width,
+    })
+}
+
+/// The formats that get resized, anything else (GIFs, SVGs, icons) is used as is
+fn resizable_format(path: &Path) -> Option<ImageFormat> {
+    match ImageFormat::from_path(path).ok()? {
+        format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP) => Some(format),
+        _ => None,
+    }
+}
+
+fn read_cache_entry(entry_dir: &Path) -> Option<CachedImage> {
+    let meta = fs::read_to_string(entry_dir.join(META_FILE)).ok()?;
+    serde_json::from_str(&meta).ok()
+}
+
+/// Resizes and encodes an image into a new cache entry
+fn generate(
+    bytes: &[u8],
+    format: ImageFormat,
+    extension: &str,
+    entry_dir: &Path,
+) -> Result<CachedImage> {
+    let image = image::load_from_memory_with_format(bytes, format)?;
+    // JPEG can't hold alpha and the WebP encoder only takes 8 bit images
+    let image = if image.color().has_alpha() && format != ImageFormat::Jpeg {
+        DynamicImage::ImageRgba8(image.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+    };
+    let (width, height) = (image.width(), image.height());
+    fs::create_dir_all(entry_dir)?;
+
+    let widths: Vec<u32> = RESPONSIVE_WIDTHS
+        .into_iter()
+        .filter(|&target| target < width)
+        .collect();
+
+    let mut webp_widths = Vec::new();
+    for target in widths.iter().copied().chain([width]) {
+        let resized = if target == width {
+            image.clone()
+        } else {
+            let target_height = (height as u64 * target as u64 / width as u64).max(1) as u32;
+            image.resize_exact(target, target_height, FilterType::CatmullRom)
+        };
+
+        let encoded = if target == width {
+            bytes.to_vec()
+        } else {
+            let encoded = encode(&resized, format)?;
+            fs::write(
+                entry_dir.join(format!("{}.{}", target, extension)),
+                &encoded,
+            )?;
+            encoded
+        };
+
+        // the WebP encoder is lossless, so photos usually come out bigger than their JPEG
+        if format != ImageFormat::WebP {
+            let webp = encode(&resized, ImageFormat::WebP)?;
+            if webp.len() < encoded.len() {
+                fs::write(entry_dir.join(format!("{}.webp", target)), webp)?;
+                webp_widths.push(target);
+            }
+        }
+    }
+
+    let cached = CachedImage {
+        width,
+        height,
+        widths,
+        webp_widths,
+    };
+    // written last, an interrupted build leaves no meta.json and the entry is made again
+    fs::write(entry_dir.join(META_FILE), serde_json::to_string(&cached)?)?;
+    Ok(cached)
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+    let mut encoded = Cursor::new(Vec::new());
+    image.write_to(&mut encoded, format)?;
+    Ok(encoded.into_inner())
+}
+
+/// A srcset attribute value with each URL made relative to the page through root
+pub fn srcset(sources: &[ImageSource], root: &str) -> String {
+    sources
+        .iter()
+        .map(|source| {
+            format!(
+                "{}{} {}w",
+                root,
+                source.url.trim_start_matches('/'),
+                source.width
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Makes the src of every asset image in a post's HTML relative to the page, so the build works
+/// from any path, and gives those with generated sizes a srcset, wrapped in a <picture> when
+/// there's a WebP set
+///
+/// # Arguments
+///
+/// * `html` - The rendered post
+/// * `images` - What ImagePipeline::process_dir generated
+/// * `root` - Path from the page to the site root, e.g. `../`
+pub fn responsive_html(html: &str, images: &HashMap<String, ImageSet>, root: &str) -> String {
+    static IMG: OnceLock<Regex> = OnceLock::new();
+    let img = IMG
+        .get_or_init(|| Regex::new(r#"<img\s(?:[^>]*?\s)?src="(/assets/[^"]+)"[^>]*>"#).unwrap());
+
+    img.replace_all(html, |caps: &Captures| {
+        let tag = &caps[0];
+        let src = format!(r#"src="{}""#, &caps[1]);
+        let relative_src = format!("{}{}", root, caps[1].trim_start_matches('/'));
+        // GIFs, SVGs and images too small to resize only need the path fixed
+        let set = match images.get(&caps[1]) {
+            Some(set) if !tag.contains("srcset=") => set,
+            _ => return tag.replacen(&src, &format!(r#"src="{}""#, relative_src), 1),
+        };
+
+        let attributes = format!(
+            r#"src="{}" srcset="{}" sizes="{}" width="{}" height="{}" loading="lazy""#,
+            relative_src,
+            srcset(&set.sources, root),
+            CONTENT_SIZES,
+            set.width,
+            set.height
+        );
+        let tag = tag.replacen(&src, &attributes, 1);
+        if set.webp.is_empty() {
+            return tag;
+        }
+        format!(
+            r#"<picture><source type="image/webp" srcset="{}" sizes="{}">{}</picture>"#,
+            srcset(&set.webp, root),
+            CONTENT_SIZES,
+            tag
+        )
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_process_dir_resizes_and_caches() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let assets = dir.path().join("assets");
+        fs::create_dir_all(&assets)?;
+        // flat colours compress well losslessly, so this one gets a WebP set
+        let image = RgbImage::from_fn(1000, 500, |x, _| Rgb([(x / 100) as u8 * 20, 80, 160]));
+        image.save(assets.join("header.png"))?;
+        // noise is what photos look like to a lossless encoder, the JPEG stays smaller
+        let photo = RgbImage::from_fn(600, 400, |x, y| {
+            let n = (x.wrapping_mul(2654435761) ^ y.wrapping_mul(40503)).wrapping_mul(2246822519);
+            Rgb([(n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8])
+        });
+        photo.save(assets.join("photo.jpg"))?;
+        fs::write(assets.join("notes.txt"), "not an image")?;
+
+        let pipeline = ImagePipeline::new(dir.path().join("cache"));
+        let out = dir.path().join("build");
+        let images = pipeline.process_dir(&assets, &out)?;
+        assert_eq!(images.len(), 2);
+
+        let set = &images["/assets/header.png"];
+        assert_eq!((set.width, set.height), (1000, 500));
+        let widths: Vec<u32> = set.sources.iter().map(|source| source.width).collect();
+        assert_eq!(widths, vec![480, 960, 1000]);
+        assert_eq!(set.sources.last().unwrap().url, "/assets/header.png");
+        let webp_widths: Vec<u32> = set.webp.iter().map(|source| source.width).collect();
+        assert_eq!(webp_widths, vec![480, 960, 1000]);
+        for source in set.sources[..2].iter().chain(&set.webp) {
+            assert!(out.join(source.url.trim_start_matches('/')).is_file());
+        }
+        let photo = &images["/assets/photo.jpg"];
+        assert_eq!(photo.sources.len(), 2);
+        assert!(photo.webp.is_empty());
+        let small = image::open(out.join(set.sources[0].url.trim_start_matches('/')))?;
+        assert_eq!((small.width(), small.height()), (480, 240));
+
+        // a second build reads the cache instead of resizing again
+        let rebuilt = pipeline.process_dir(&assets, &dir.path().join("build-2"))?;
+        assert_eq!(rebuilt["/assets/header.png"], *set);
+
+        let html = responsive_html(
+            r#"<p><img src="/assets/header.png" alt="Header" /><img src="https://example.com/a.png" alt="" /></p>"#,
+            &images,
+            "../",
+        );
+        assert!(
+            html.starts_with(r#"<p><picture><source type="image/webp" srcset="../assets/sizes/"#)
+        );
+        assert!(html.contains(r#"<img src="../assets/header.png" srcset="../assets/sizes/"#));
+        assert!(html.contains(", ../assets/header.png 1000w\""));
+        assert!(html.contains(r#"<img src="https://example.com/a.png" alt="" />"#));
+        // no WebP set, no <picture>
+        let html = responsive_html(r#"<img src="/assets/photo.jpg" alt="" />"#, &images, "");
+        assert!(html.starts_with(r#"<img src="assets/photo.jpg" srcset="assets/sizes/"#));
+        // images without generated sizes still get a path that works from any directory
+        let html = responsive_html(
+            r#"<figure><img src="/assets/spinner.gif" alt="Loading" loading="lazy"></figure>"#,
+            &images,
+            "../",
+        );
+        assert_eq!(
+            html,
+            r#"<figure><img src="../assets/spinner.gif" alt="Loading" loading="lazy"></figure>"#
+        );
+        Ok(())
+    }
+}
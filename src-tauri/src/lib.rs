@@ -1,20 +1,40 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+pub mod assets;
+pub mod builder;
 pub mod commands;
 pub mod crypto;
 pub mod db;
+pub mod deploy;
 pub mod driftwood;
+pub mod favicons;
+pub mod headers;
+pub mod highlight;
+pub mod images;
+pub mod math;
 pub mod netlify;
 pub mod posts;
+pub mod preview;
+pub mod redirects;
 pub mod response;
+pub mod s3;
+pub mod search_index;
+pub mod shortcodes;
 pub mod sites;
+pub mod stats;
+pub mod themes;
 pub mod users;
+pub mod wordpress;
 
 use crate::commands::{
     check_token, create_post, create_site, delete_post, delete_site, deploy_site, get_post_details,
     get_post_list, get_recent_posts, get_site_details, list_sites, netlify_login, netlify_logout,
-    refresh_sites, update_post, update_site, get_post_count,
+    refresh_sites, update_post, update_site, get_post_count, import_wordpress, import_post_image,
+    deploy_site_to, start_preview, stop_preview, list_themes, get_site_settings,
+    update_site_settings, list_highlight_themes, list_redirects, add_redirect, delete_redirect,
+    list_headers, add_header, delete_header, get_site_stats,
+    search_posts,
 };
 
 use dotenv::dotenv;
@@ -50,6 +70,23 @@ pub fn run() {
             update_post,
             get_recent_posts,
             get_post_count,
+            get_site_stats,
+            search_posts,
+            import_wordpress,
+            import_post_image,
+            deploy_site_to,
+            start_preview,
+            stop_preview,
+            list_themes,
+            get_site_settings,
+            update_site_settings,
+            list_highlight_themes,
+            list_redirects,
+            add_redirect,
+            delete_redirect,
+            list_headers,
+            add_header,
+            delete_header,
         ])
         .plugin(tauri_plugin_dialog::init())
         .run(tauri::generate_context!())
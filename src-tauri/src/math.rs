@@ -0,0 +1,926 @@
+/// Converts the LaTeX in `$...$` and `$$...$$` to MathML while building, so equations
+/// render in every browser without KaTeX or MathJax on the page.
+/// Covers the LaTeX people write in blog posts: scripts, fractions, roots, Greek letters,
+/// operators, accents, fonts, \left...\right and the matrix, cases and aligned environments
+use std::fmt::Write;
+
+/// How deep groups and commands can nest, formulas past this are almost certainly not math
+/// and would otherwise overflow the stack
+const MAX_DEPTH: usize = 128;
+
+/// Converts one formula
+///
+/// # Arguments
+///
+/// * `latex` - The formula, without the dollar signs
+/// * `display` - Whether it's a display (`$$`) formula, rendered as its own block
+///
+/// # Returns
+///
+/// A `<math>` element, or a message saying what in the formula isn't supported
+pub fn latex_to_mathml(latex: &str, display: bool) -> Result<String, String> {
+    let mut parser = MathParser {
+        chars: latex.chars().collect(),
+        pos: 0,
+        display,
+        font: None,
+        depth: 0,
+    };
+    let (items, end) = parser.parse_row()?;
+    match end {
+        RowEnd::Eof => {}
+        RowEnd::CloseBrace => return Err(String::from("Unmatched }")),
+        other => return Err(format!("Unexpected {}", other.describe())),
+    }
+
+    Ok(format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\"{}><semantics><mrow>{}</mrow><annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        if display { " display=\"block\"" } else { "" },
+        items.concat(),
+        escape(latex.trim())
+    ))
+}
+
+/// What stopped a row from being parsed further
+#[derive(Debug, PartialEq)]
+enum RowEnd {
+    Eof,
+    CloseBrace,
+    CloseBracket,
+    Right,
+    Ampersand,
+    NewRow,
+    End(String),
+}
+
+impl RowEnd {
+    fn describe(&self) -> String {
+        match self {
+            RowEnd::Eof => String::from("end of formula"),
+            RowEnd::CloseBrace => String::from("}"),
+            RowEnd::CloseBracket => String::from("]"),
+            RowEnd::Right => String::from("\\right"),
+            RowEnd::Ampersand => String::from("& outside of an environment"),
+            RowEnd::NewRow => String::from("\\\\ outside of an environment"),
+            RowEnd::End(name) => format!("\\end{{{}}}", name),
+        }
+    }
+}
+
+/// Letter styles from \mathbb, \mathbf and friends
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Font {
+    Roman,
+    Bold,
+    Italic,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    SansSerif,
+    Monospace,
+}
+
+/// A parsed piece of a formula
+/// limits: Big operators like \sum take their scripts above and below in display formulas
+struct Atom {
+    html: String,
+    limits: bool,
+}
+
+impl Atom {
+    fn new(html: String) -> Atom {
+        Atom {
+            html,
+            limits: false,
+        }
+    }
+}
+
+struct MathParser {
+    chars: Vec<char>,
+    pos: usize,
+    display: bool,
+    font: Option<Font>,
+    depth: usize,
+}
+
+impl MathParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// The command name starting at pos, which is just after a backslash
+    fn peek_command(&self) -> String {
+        let letters: String = self.chars[self.pos..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect();
+        if letters.is_empty() {
+            self.peek().map(String::from).unwrap_or_default()
+        } else {
+            letters
+        }
+    }
+
+    /// Parses atoms and their scripts until a closing brace, \right, &, \\ or \end
+    fn parse_row(&mut self) -> Result<(Vec<String>, RowEnd), String> {
+        self.parse_row_until(false)
+    }
+
+    fn parse_row_until(&mut self, bracket: bool) -> Result<(Vec<String>, RowEnd), String> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let end = match self.peek() {
+                None => Some(RowEnd::Eof),
+                Some('}') => Some(RowEnd::CloseBrace),
+                Some(']') if bracket => Some(RowEnd::CloseBracket),
+                Some('&') => Some(RowEnd::Ampersand),
+                Some('\\') => {
+                    self.pos += 1;
+                    let name = self.peek_command();
+                    match name.as_str() {
+                        "right" => Some(RowEnd::Right),
+                        "\\" => Some(RowEnd::NewRow),
+                        "end" => {
+                            self.pos += name.len();
+                            Some(RowEnd::End(self.parse_environment_name()?))
+                        }
+                        _ => {
+                            self.pos -= 1;
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            if let Some(end) = end {
+                match end {
+                    RowEnd::Eof | RowEnd::End(_) => {}
+                    RowEnd::Right => self.pos += "right".len(),
+                    _ => self.pos += 1,
+                }
+                return Ok((items, end));
+            }
+
+            let base = match self.peek() {
+                // a script with nothing before it, like {}^{14}C
+                Some('^') | Some('_') => Atom::new(String::from("<mrow></mrow>")),
+                _ => self.parse_atom(false)?,
+            };
+            items.push(self.parse_scripts(base)?);
+        }
+    }
+
+    /// Attaches any ^ and _ that follow an atom
+    fn parse_scripts(&mut self, base: Atom) -> Result<String, String> {
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.parse_argument()?);
+                }
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.parse_argument()?);
+                }
+                Some('\'') if sup.is_none() => {
+                    let mut primes = String::new();
+                    while self.peek() == Some('\'') {
+                        self.pos += 1;
+                        primes.push('′');
+                    }
+                    sup = Some(format!("<mo>{}</mo>", primes));
+                }
+                _ => break,
+            }
+        }
+
+        let under = base.limits && self.display;
+        let html = base.html;
+        Ok(match (sub, sup) {
+            (None, None) => html,
+            (Some(sub), None) if under => format!("<munder>{}{}</munder>", html, sub),
+            (None, Some(sup)) if under => format!("<mover>{}{}</mover>", html, sup),
+            (Some(sub), Some(sup)) if under => {
+                format!("<munderover>{}{}{}</munderover>", html, sub, sup)
+            }
+            (Some(sub), None) => format!("<msub>{}{}</msub>", html, sub),
+            (None, Some(sup)) => format!("<msup>{}{}</msup>", html, sup),
+            (Some(sub), Some(sup)) => format!("<msubsup>{}{}{}</msubsup>", html, sub, sup),
+        })
+    }
+
+    /// The argument of a command or script, a {group} or a single token
+    fn parse_argument(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(String::from("Missing argument at end of formula")),
+            Some('{') => {
+                self.pos += 1;
+                self.parse_group()
+            }
+            Some(_) => Ok(self.parse_atom(true)?.html),
+        }
+    }
+
+    /// Runs a parse one level deeper, or fails once formulas nest past MAX_DEPTH
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(String::from("Formula is nested too deeply"));
+        }
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    /// Parses up to the closing brace, the opening one already consumed
+    fn parse_group(&mut self) -> Result<String, String> {
+        self.nested(Self::parse_group_items)
+    }
+
+    fn parse_group_items(&mut self) -> Result<String, String> {
+        let (items, end) = self.parse_row()?;
+        if end != RowEnd::CloseBrace {
+            return Err(format!("Expected }} but found {}", end.describe()));
+        }
+        Ok(match items.len() {
+            1 => items.into_iter().next().unwrap(),
+            _ => format!("<mrow>{}</mrow>", items.concat()),
+        })
+    }
+
+    /// Reads `{text}` as is, for \text and environment names
+    fn parse_raw_group(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.peek() != Some('{') {
+            return Err(String::from("Expected {"));
+        }
+        self.pos += 1;
+        let mut depth = 1;
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(text);
+                    }
+                }
+                _ => {}
+            }
+            text.push(c);
+        }
+        Err(String::from("Unmatched {"))
+    }
+
+    fn parse_environment_name(&mut self) -> Result<String, String> {
+        Ok(self.parse_raw_group()?.trim().to_string())
+    }
+
+    /// Parses one token, or one digit when `single` is set, e.g. the 1 in x^12
+    fn parse_atom(&mut self, single: bool) -> Result<Atom, String> {
+        self.nested(|parser| parser.parse_token(single))
+    }
+
+    fn parse_token(&mut self, single: bool) -> Result<Atom, String> {
+        self.skip_whitespace();
+        let c = self
+            .peek()
+            .ok_or_else(|| String::from("Unexpected end of formula"))?;
+        self.pos += 1;
+
+        Ok(match c {
+            '{' => Atom::new(self.parse_group()?),
+            '\\' => return self.parse_command(),
+            c if c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while !single
+                    && self
+                        .peek()
+                        .is_some_and(|next| next.is_ascii_digit() || next == '.')
+                {
+                    number.push(self.peek().unwrap());
+                    self.pos += 1;
+                }
+                Atom::new(format!("<mn>{}</mn>", self.styled(&number)))
+            }
+            c if c.is_alphabetic() => Atom::new(self.identifier(c)),
+            '~' => Atom::new(String::from("<mspace width=\"0.3333em\"></mspace>")),
+            '\'' => Atom::new(String::from("<mo>′</mo>")),
+            c => Atom::new(format!("<mo>{}</mo>", escape(&c.to_string()))),
+        })
+    }
+
+    fn identifier(&self, c: char) -> String {
+        match self.font {
+            Some(Font::Roman) => {
+                format!("<mi mathvariant=\"normal\">{}</mi>", escape(&c.to_string()))
+            }
+            _ => format!("<mi>{}</mi>", self.styled(&c.to_string())),
+        }
+    }
+
+    /// Maps letters and digits to their Unicode mathematical style for the current font
+    fn styled(&self, text: &str) -> String {
+        match self.font {
+            Some(font) => text.chars().map(|c| styled_char(c, font)).collect(),
+            None => escape(text),
+        }
+    }
+
+    /// Parses a command, the backslash already consumed
+    fn parse_command(&mut self) -> Result<Atom, String> {
+        let name = self.peek_command();
+        if name.is_empty() {
+            return Err(String::from("Formula ends with a backslash"));
+        }
+        self.pos += name.chars().count();
+
+        if let Some(symbol) = identifier_symbol(&name) {
+            return Ok(Atom::new(format!("<mi>{}</mi>", symbol)));
+        }
+        if let Some(symbol) = operator_symbol(&name) {
+            return Ok(Atom::new(format!("<mo>{}</mo>", escape(symbol))));
+        }
+        if let Some(symbol) = big_operator(&name) {
+            return Ok(Atom {
+                html: format!("<mo>{}</mo>", symbol),
+                // integrals keep their limits at the side
+                limits: !symbol.starts_with(['∫', '∬', '∭', '∮']),
+            });
+        }
+        if FUNCTIONS.contains(&name.as_str()) {
+            return Ok(Atom::new(format!("<mi>{}</mi>", name)));
+        }
+        if let Some(text) = limit_function(&name) {
+            return Ok(Atom {
+                html: format!("<mo movablelimits=\"true\" form=\"prefix\">{}</mo>", text),
+                limits: true,
+            });
+        }
+        if let Some(width) = space_width(&name) {
+            return Ok(Atom::new(format!("<mspace width=\"{}\"></mspace>", width)));
+        }
+        if let Some(font) = font_command(&name) {
+            let outer = self.font.replace(font);
+            let argument = self.parse_argument();
+            self.font = outer;
+            return Ok(Atom::new(argument?));
+        }
+        if let Some((accent, over)) = accent_command(&name) {
+            let argument = self.parse_argument()?;
+            let tag = if over { "mover" } else { "munder" };
+            let attribute = if over { "accent" } else { "accentunder" };
+            return Ok(Atom::new(format!(
+                "<{0} {1}=\"true\">{2}<mo stretchy=\"true\">{3}</mo></{0}>",
+                tag, attribute, argument, accent
+            )));
+        }
+
+        let html = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.parse_argument()?;
+                let denominator = self.parse_argument()?;
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let top = self.parse_argument()?;
+                let bottom = self.parse_argument()?;
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    top, bottom
+                )
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                if self.peek() == Some('[') {
+                    self.pos += 1;
+                    let (index, end) = self.parse_row_until(true)?;
+                    if end != RowEnd::CloseBracket {
+                        return Err(String::from("Unmatched [ in \\sqrt"));
+                    }
+                    let radicand = self.parse_argument()?;
+                    format!("<mroot>{}<mrow>{}</mrow></mroot>", radicand, index.concat())
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_argument()?)
+                }
+            }
+            "overset" | "stackrel" | "underset" => {
+                let script = self.parse_argument()?;
+                let base = self.parse_argument()?;
+                let tag = if name == "underset" {
+                    "munder"
+                } else {
+                    "mover"
+                };
+                format!("<{0}>{1}{2}</{0}>", tag, base, script)
+            }
+            "text" | "textrm" | "textit" | "textbf" | "mbox" => {
+                let text = self.parse_raw_group()?;
+                let variant = match name.as_str() {
+                    "textit" => " mathvariant=\"italic\"",
+                    "textbf" => " mathvariant=\"bold\"",
+                    _ => "",
+                };
+                format!("<mtext{}>{}</mtext>", variant, escape(&text))
+            }
+            "operatorname" => {
+                let text = self.parse_raw_group()?;
+                format!("<mi>{}</mi>", escape(text.trim()))
+            }
+            "left" => return self.parse_fenced(),
+            "begin" => {
+                let environment = self.parse_environment_name()?;
+                self.parse_environment(&environment)?
+            }
+            "not" => {
+                let negated = self.parse_atom(true)?.html;
+                match negated.as_str() {
+                    "<mo>=</mo>" => String::from("<mo>≠</mo>"),
+                    "<mo>∈</mo>" => String::from("<mo>∉</mo>"),
+                    "<mo>⊂</mo>" => String::from("<mo>⊄</mo>"),
+                    _ => format!(
+                        "<menclose notation=\"updiagonalstrike\">{}</menclose>",
+                        negated
+                    ),
+                }
+            }
+            // layout hints browsers work out for themselves
+            "displaystyle" | "textstyle" | "limits" | "nolimits" | "nonumber" => String::new(),
+            "{" | "}" | "|" | "%" | "$" | "#" | "&" | "_" => {
+                let symbol = if name == "|" { "‖" } else { &name };
+                format!("<mo>{}</mo>", escape(symbol))
+            }
+            _ => return Err(format!("Unsupported command \\{}", name)),
+        };
+        Ok(Atom::new(html))
+    }
+
+    /// \left( ... \right), the \left already consumed
+    fn parse_fenced(&mut self) -> Result<Atom, String> {
+        let open = self.parse_delimiter()?;
+        let (items, end) = self.parse_row()?;
+        if end != RowEnd::Right {
+            return Err(String::from("\\left without a matching \\right"));
+        }
+        let close = self.parse_delimiter()?;
+
+        let fence = |delimiter: &str| match delimiter {
+            "" => String::new(),
+            delimiter => format!(
+                "<mo fence=\"true\" stretchy=\"true\">{}</mo>",
+                escape(delimiter)
+            ),
+        };
+        Ok(Atom::new(format!(
+            "<mrow>{}{}{}</mrow>",
+            fence(&open),
+            items.concat(),
+            fence(&close)
+        )))
+    }
+
+    /// The delimiter after \left or \right, "." is an invisible one
+    fn parse_delimiter(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let c = self
+            .peek()
+            .ok_or_else(|| String::from("Missing delimiter after \\left or \\right"))?;
+        self.pos += 1;
+        if c == '.' {
+            return Ok(String::new());
+        }
+        if c != '\\' {
+            return Ok(c.to_string());
+        }
+
+        let name = self.peek_command();
+        self.pos += name.chars().count();
+        match name.as_str() {
+            "{" | "}" => Ok(name),
+            "|" | "Vert" => Ok(String::from("‖")),
+            _ => operator_symbol(&name)
+                .map(String::from)
+                .ok_or_else(|| format!("Unsupported delimiter \\{}", name)),
+        }
+    }
+
+    /// Parses a \begin{...} environment into a table, the \begin{name} already consumed
+    fn parse_environment(&mut self, environment: &str) -> Result<String, String> {
+        let (open, close, align) = match environment {
+            "matrix" | "smallmatrix" => ("", "", ""),
+            "pmatrix" => ("(", ")", ""),
+            "bmatrix" => ("[", "]", ""),
+            "Bmatrix" => ("{", "}", ""),
+            "vmatrix" => ("|", "|", ""),
+            "Vmatrix" => ("‖", "‖", ""),
+            "cases" => ("{", "", " columnalign=\"left left\""),
+            "aligned" | "align" | "align*" | "split" | "gathered" => {
+                ("", "", " columnalign=\"right left\"")
+            }
+            "array" => {
+                // column spec, e.g. {cc|c}, isn't needed for the layout
+                self.parse_raw_group()?;
+                ("", "", "")
+            }
+            _ => return Err(format!("Unsupported environment {}", environment)),
+        };
+
+        let mut rows = String::new();
+        let mut row = String::new();
+        loop {
+            let (items, end) = self.parse_row()?;
+            let _ = write!(row, "<mtd>{}</mtd>", items.concat());
+            match end {
+                RowEnd::Ampersand => continue,
+                RowEnd::NewRow => {
+                    let _ = write!(rows, "<mtr>{}</mtr>", row);
+                    row.clear();
+                }
+                RowEnd::End(name) if name == environment => {
+                    // a trailing \\ leaves an empty last row behind
+                    if row != "<mtd></mtd>" {
+                        let _ = write!(rows, "<mtr>{}</mtr>", row);
+                    }
+                    break;
+                }
+                other => {
+                    return Err(format!(
+                        "Expected \\end{{{}}} but found {}",
+                        environment,
+                        other.describe()
+                    ))
+                }
+            }
+        }
+
+        let table = format!("<mtable{}>{}</mtable>", align, rows);
+        let fence = |delimiter: &str| match delimiter {
+            "" => String::new(),
+            delimiter => format!("<mo fence=\"true\">{}</mo>", escape(delimiter)),
+        };
+        Ok(format!(
+            "<mrow>{}{}{}</mrow>",
+            fence(open),
+            table,
+            fence(close)
+        ))
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Functions written upright, like \sin x
+const FUNCTIONS: [&str; 22] = [
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "log", "ln", "lg", "exp", "det", "dim", "ker", "deg", "arg",
+];
+
+/// Functions whose subscript goes underneath in display formulas, like \lim_{x \to 0}
+fn limit_function(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "lim" => "lim",
+        "limsup" => "lim sup",
+        "liminf" => "lim inf",
+        "max" => "max",
+        "min" => "min",
+        "sup" => "sup",
+        "inf" => "inf",
+        "gcd" => "gcd",
+        "Pr" => "Pr",
+        _ => return None,
+    })
+}
+
+fn identifier_symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" => "ϵ",
+        "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "vartheta" => "ϑ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "varpi" => "ϖ",
+        "rho" => "ρ",
+        "varrho" => "ϱ",
+        "sigma" => "σ",
+        "varsigma" => "ς",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" => "ϕ",
+        "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "emptyset" | "varnothing" => "∅",
+        "hbar" => "ℏ",
+        "ell" => "ℓ",
+        "Re" => "ℜ",
+        "Im" => "ℑ",
+        "aleph" => "ℵ",
+        _ => return None,
+    })
+}
+
+fn operator_symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "times" => "×",
+        "cdot" => "⋅",
+        "pm" => "±",
+        "mp" => "∓",
+        "div" => "÷",
+        "ast" => "∗",
+        "star" => "⋆",
+        "circ" => "∘",
+        "bullet" => "∙",
+        "leq" | "le" => "≤",
+        "geq" | "ge" => "≥",
+        "neq" | "ne" => "≠",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "cong" => "≅",
+        "propto" => "∝",
+        "ll" => "≪",
+        "gg" => "≫",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "Rightarrow" => "⇒",
+        "Leftarrow" => "⇐",
+        "leftrightarrow" => "↔",
+        "Leftrightarrow" => "⇔",
+        "iff" | "Longleftrightarrow" => "⟺",
+        "implies" | "Longrightarrow" => "⟹",
+        "longrightarrow" => "⟶",
+        "mapsto" => "↦",
+        "uparrow" => "↑",
+        "downarrow" => "↓",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "supset" => "⊃",
+        "subseteq" => "⊆",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "forall" => "∀",
+        "exists" => "∃",
+        "neg" | "lnot" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "perp" => "⊥",
+        "parallel" => "∥",
+        "mid" => "∣",
+        "cdots" => "⋯",
+        "ldots" | "dots" => "…",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "prime" => "′",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "lvert" | "rvert" | "vert" => "|",
+        "lVert" | "rVert" | "Vert" => "‖",
+        "colon" => ":",
+        "angle" => "∠",
+        "triangle" => "△",
+        "therefore" => "∴",
+        "because" => "∵",
+        _ => return None,
+    })
+}
+
+fn big_operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        "bigvee" => "⋁",
+        "bigwedge" => "⋀",
+        _ => return None,
+    })
+}
+
+fn space_width(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "," | "thinspace" => "0.1667em",
+        ":" | ">" | "medspace" => "0.2222em",
+        ";" | "thickspace" => "0.2778em",
+        " " => "0.25em",
+        "!" => "-0.1667em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        _ => return None,
+    })
+}
+
+fn font_command(name: &str) -> Option<Font> {
+    Some(match name {
+        "mathrm" => Font::Roman,
+        "mathbf" | "boldsymbol" | "bm" => Font::Bold,
+        "mathit" => Font::Italic,
+        "mathbb" => Font::DoubleStruck,
+        "mathcal" | "mathscr" => Font::Script,
+        "mathfrak" => Font::Fraktur,
+        "mathsf" => Font::SansSerif,
+        "mathtt" => Font::Monospace,
+        _ => return None,
+    })
+}
+
+/// The accent character for a command, and whether it goes over (or under) its argument
+fn accent_command(name: &str) -> Option<(&'static str, bool)> {
+    Some(match name {
+        "hat" | "widehat" => ("^", true),
+        "bar" | "overline" => ("¯", true),
+        "vec" | "overrightarrow" => ("→", true),
+        "overleftarrow" => ("←", true),
+        "dot" => ("˙", true),
+        "ddot" => ("¨", true),
+        "tilde" | "widetilde" => ("~", true),
+        "check" => ("ˇ", true),
+        "breve" => ("˘", true),
+        "acute" => ("´", true),
+        "grave" => ("`", true),
+        "overbrace" => ("⏞", true),
+        "underline" => ("_", false),
+        "underbrace" => ("⏟", false),
+        _ => return None,
+    })
+}
+
+/// A letter or digit in one of the Unicode mathematical alphanumeric styles
+fn styled_char(c: char, font: Font) -> String {
+    // letters that were in Unicode before the mathematical block, so it has holes where they'd be
+    let exception = match (font, c) {
+        (Font::DoubleStruck, 'C') => Some('ℂ'),
+        (Font::DoubleStruck, 'H') => Some('ℍ'),
+        (Font::DoubleStruck, 'N') => Some('ℕ'),
+        (Font::DoubleStruck, 'P') => Some('ℙ'),
+        (Font::DoubleStruck, 'Q') => Some('ℚ'),
+        (Font::DoubleStruck, 'R') => Some('ℝ'),
+        (Font::DoubleStruck, 'Z') => Some('ℤ'),
+        (Font::Script, 'B') => Some('ℬ'),
+        (Font::Script, 'E') => Some('ℰ'),
+        (Font::Script, 'F') => Some('ℱ'),
+        (Font::Script, 'H') => Some('ℋ'),
+        (Font::Script, 'I') => Some('ℐ'),
+        (Font::Script, 'L') => Some('ℒ'),
+        (Font::Script, 'M') => Some('ℳ'),
+        (Font::Script, 'R') => Some('ℛ'),
+        (Font::Script, 'e') => Some('ℯ'),
+        (Font::Script, 'g') => Some('ℊ'),
+        (Font::Script, 'o') => Some('ℴ'),
+        (Font::Fraktur, 'C') => Some('ℭ'),
+        (Font::Fraktur, 'H') => Some('ℌ'),
+        (Font::Fraktur, 'I') => Some('ℑ'),
+        (Font::Fraktur, 'R') => Some('ℜ'),
+        (Font::Fraktur, 'Z') => Some('ℨ'),
+        (Font::Italic, 'h') => Some('ℎ'),
+        _ => None,
+    };
+    if let Some(exception) = exception {
+        return exception.to_string();
+    }
+
+    // start of the capital letters, small letters and digits for each style
+    let (upper, lower, digits) = match font {
+        Font::Roman => return escape(&c.to_string()),
+        Font::Bold => (0x1D400, 0x1D41A, Some(0x1D7CE)),
+        Font::Italic => (0x1D434, 0x1D44E, None),
+        Font::Script => (0x1D49C, 0x1D4B6, None),
+        Font::Fraktur => (0x1D504, 0x1D51E, None),
+        Font::DoubleStruck => (0x1D538, 0x1D552, Some(0x1D7D8)),
+        Font::SansSerif => (0x1D5A0, 0x1D5BA, Some(0x1D7E2)),
+        Font::Monospace => (0x1D670, 0x1D68A, Some(0x1D7F6)),
+    };
+    let code = match c {
+        'A'..='Z' => Some(upper + (c as u32 - 'A' as u32)),
+        'a'..='z' => Some(lower + (c as u32 - 'a' as u32)),
+        '0'..='9' => digits.map(|start| start + (c as u32 - '0' as u32)),
+        _ => None,
+    };
+    match code.and_then(char::from_u32) {
+        Some(styled) => styled.to_string(),
+        None => escape(&c.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inner(latex: &str, display: bool) -> String {
+        let mathml = latex_to_mathml(latex, display).unwrap();
+        let start = mathml.find("<semantics><mrow>").unwrap() + "<semantics><mrow>".len();
+        let end = mathml.rfind("</mrow><annotation").unwrap();
+        mathml[start..end].to_string()
+    }
+
+    #[test]
+    fn test_latex_to_mathml() {
+        assert_eq!(
+            inner("x^2 + y_1", false),
+            "<msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><msub><mi>y</mi><mn>1</mn></msub>"
+        );
+        assert_eq!(
+            inner(r"\frac{a}{b} \leq \sqrt[3]{x}", false),
+            "<mfrac><mi>a</mi><mi>b</mi></mfrac><mo>≤</mo><mroot><mi>x</mi><mrow><mn>3</mn></mrow></mroot>"
+        );
+        // limits go under big operators only in display formulas
+        assert!(inner(r"\sum_{i=1}^n i", true).starts_with("<munderover><mo>∑</mo>"));
+        assert!(inner(r"\sum_{i=1}^n i", false).starts_with("<msubsup><mo>∑</mo>"));
+        assert_eq!(
+            inner(r"\mathbb{R}^n", false),
+            "<msup><mi>ℝ</mi><mi>n</mi></msup>"
+        );
+        assert_eq!(
+            inner(r"\left( \alpha \right.", false),
+            "<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mi>α</mi></mrow>"
+        );
+        assert_eq!(
+            inner(r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \end{pmatrix}", true),
+            "<mrow><mo fence=\"true\">(</mo><mtable><mtr><mtd><mn>1</mn></mtd><mtd><mn>0</mn></mtd></mtr><mtr><mtd><mn>0</mn></mtd><mtd><mn>1</mn></mtd></mtr></mtable><mo fence=\"true\">)</mo></mrow>"
+        );
+        assert_eq!(
+            inner(r"a < b \text{ if } c", false),
+            "<mi>a</mi><mo>&lt;</mo><mi>b</mi><mtext> if </mtext><mi>c</mi>"
+        );
+
+        let block = latex_to_mathml("E = mc^2", true).unwrap();
+        assert!(block
+            .starts_with(r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="block">"#));
+        assert!(block.contains(r#"<annotation encoding="application/x-tex">E = mc^2</annotation>"#));
+
+        assert!(latex_to_mathml(r"\frac{a}{b", false).is_err());
+        assert!(latex_to_mathml(r"\unknowncommand", false).is_err());
+        assert!(latex_to_mathml(r"\left( x", false).is_err());
+        // deep nesting is an error, not a stack overflow
+        assert!(latex_to_mathml(&"{".repeat(100_000), false).is_err());
+        assert!(latex_to_mathml(&r"\sqrt".repeat(100_000), false).is_err());
+        assert!(latex_to_mathml(&"x^".repeat(100_000), false).is_err());
+        assert!(latex_to_mathml(&format!("{}x{}", "{".repeat(60), "}".repeat(60)), false).is_ok());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::builder::list_files;
 use crate::crypto;
 use crate::driftwood::{NewSite, OAuth2, SiteDetails};
 use reqwest::Url;
@@ -266,10 +267,14 @@ impl Netlify {
         self.read_object_response(response)
     }
 
+    /// Upload a single file to a deploy
+    /// build_root: The directory the site was built into
+    /// deploy_id: The ID of the deploy created by send_file_checksums
+    /// file_path: The file's path in the deploy, e.g. /posts/hello.html
     pub fn upload_file(
         &self,
-        site_id: String,
-        deploy_id: String,
+        build_root: &Path,
+        deploy_id: &str,
         file_path: &Path,
     ) -> Result<SiteDetails, Box<dyn std::error::Error>> {
         // create the url
@@ -283,19 +288,8 @@ impl Netlify {
         println!("> request URL: {}", request_url);
         println!("> File path: {}", file_path.display());
 
-        let full_path_str: String;
-        let full_path;
-
-        // if the file is /index.html, full path will be sitename_siteid/index.html
-        if file_path.to_string_lossy() == "/index.html" {
-            println!("> File is index.html");
-            full_path_str = format!("sites/{}/index.html", site_id);
-            full_path = Path::new(&full_path_str);
-        } else {
-            println!("> File is not index.html");
-            full_path_str = format!("sites/{}{}", site_id, file_path.display());
-            full_path = Path::new(&full_path_str);
-        }
+        // the deploy path is relative to the build directory
+        let full_path = build_root.join(file_path.strip_prefix("/").unwrap_or(file_path));
 
         println!("> Full path: {}", full_path.display());
 
@@ -588,62 +582,28 @@ impl Netlify {
         headers
     }
 
-    /// Reads in all files in a site's posts directory and generates SHA1 hashes
+    /// Reads in all files in a site's build directory and generates SHA1 hashes
     /// Returns a FileHashes struct containing the path and SHA1 hash of a file
     pub fn generate_sha1_for_posts(
-        site_path: &Path,
-        posts_dir: &Path,
+        build_root: &Path,
     ) -> Result<FileHashes, Box<dyn std::error::Error>> {
         println!("> Generating SHA1 hashes for posts...");
-        println!("> Posts directory: {:?}", posts_dir);
+        println!("> Build directory: {:?}", build_root);
 
         let mut file_hashes = FileHashes {
             files: HashMap::new(),
         };
 
-        let mut sha1 = sha1_smol::Sha1::new();
-
         // ensure the index.html file exists
-        if !Path::new(&format!("{}/index.html", site_path.display())).exists() {
-            return Err(format!("> index.html not found in {}", site_path.display()).into());
+        if !build_root.join("index.html").exists() {
+            return Err(format!("> index.html not found in {}", build_root.display()).into());
         }
 
-        // first grab the hash for /site/index.html
-        let index_file = fs::read(format!("{}/index.html", site_path.display()))?;
-
-        sha1.update(&index_file);
-        file_hashes
-            .files
-            .insert("/index.html".to_string(), sha1.digest().to_string());
-        sha1.reset();
-
-        // if the favicon.ico file exists, hash that as well.
-        if Path::new(&format!("{}/favicon.ico", site_path.display())).exists() {
-            println!("favicon file exists, hashing");
-            // first grab the hash for /site/index.html
-            let favicon_file = fs::read(format!("{}/favicon.ico", site_path.display()))?;
-
-            sha1.update(&favicon_file);
-            file_hashes
-                .files
-                .insert("/favicon.ico".to_string(), sha1.digest().to_string());
-            sha1.reset();
-        }
-
-        // loop through the files in this dir
-        for entry in fs::read_dir(posts_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
-                // provide sha1.update with the file
-                let file = fs::read(path)?;
-                sha1.update(&file);
-                file_hashes
-                    .files
-                    .insert(format!("/posts/{}", file_name), sha1.digest().to_string());
-                sha1.reset();
-            }
+        // hash everything in the build, index.html, posts, favicon.ico and assets alike
+        for file_name in list_files(build_root)? {
+            let file = fs::read(build_root.join(file_name.trim_start_matches('/')))?;
+            let hash = sha1_smol::Sha1::from(&file).digest().to_string();
+            file_hashes.files.insert(file_name, hash);
         }
 
         println!("{:?}", file_hashes);
@@ -1,7 +1,51 @@
-use crate::{db::DB_PATH, driftwood::Post};
-use rusqlite::{params, Connection, OptionalExtension, Result};
+use crate::{
+    db::DB_PATH,
+    driftwood::{is_valid_slug, slugify, MarkdownOptions, Post},
+    stats::{PostStats, SiteStats},
+};
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+use serde::Serialize;
 use std::path::Path;
 
+/// Columns read into a Post, in the order post_from_row expects them
+const POST_COLUMNS: &str =
+    "title, header_image, date, content, post_id, site_id, excerpt, tags, slug,
+     word_count, reading_time, heading_count, image_count, link_count";
+
+/// Columns holding a post's PostStats, in the struct's order
+pub const STATS_COLUMNS: [&str; 5] = [
+    "word_count",
+    "reading_time",
+    "heading_count",
+    "image_count",
+    "link_count",
+];
+
+/// Used when a title has nothing a slug can be made from, e.g. "???"
+pub const FALLBACK_SLUG: &str = "post";
+
+/// Most results a search returns
+pub const SEARCH_LIMIT: u32 = 50;
+
+/// Marks matches in snippets while they're still plain text, so the text can be escaped
+const MATCH_START: &str = "\u{E000}";
+const MATCH_END: &str = "\u{E001}";
+
+/// SearchResult struct
+/// A post that matched a search
+/// snippet: The part of the content that matched best, HTML escaped with matches wrapped in <mark>
+/// rank: How well it matched, lower is better
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SearchResult {
+    pub post_id: u64,
+    pub site_id: String,
+    pub site_name: Option<String>,
+    pub title: String,
+    pub slug: String,
+    pub snippet: String,
+    pub rank: f64,
+}
+
 pub struct PostRepository {
     conn: Connection,
 }
@@ -12,47 +56,172 @@ impl PostRepository {
         Ok(Self { conn })
     }
 
-    pub fn create(&self, post: &Post, site_id: &str) -> Result<()> {
+    /// Inserts a new post, its slug comes from post.slug or the title and is made unique within the site
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The site's Markdown extensions, the post's statistics are counted with them
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the slug the post was saved with
+    pub fn create(&self, post: &Post, site_id: &str, options: &MarkdownOptions) -> Result<String> {
+        let slug = unique_slug(&self.conn, site_id, &slug_base(&post.slug, &post.title), 0)?;
         self.conn.execute(
-            "INSERT INTO posts (title, site_id, header_image, date, content)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![post.title, site_id, post.image, post.date, post.content],
+            "INSERT INTO posts (title, site_id, header_image, date, content, excerpt, tags, slug)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                post.title,
+                site_id,
+                post.image,
+                post.date,
+                post.content,
+                post.excerpt,
+                post.tags.join(","),
+                slug,
+            ],
         )?;
-        Ok(())
+        let post_id = self.conn.last_insert_rowid() as u64;
+        self.record_slug_change(site_id, post_id, None, &slug)?;
+        save_stats(&self.conn, post_id, &post.content, options)?;
+        Ok(slug)
     }
 
     pub fn read(&self, site_id: &str, post_id: u64) -> Result<Option<Post>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT title, header_image, date, content, post_id, site_id, excerpt FROM posts WHERE site_id = ?1 AND post_id = ?2"
-        )?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM posts WHERE site_id = ?1 AND post_id = ?2",
+            POST_COLUMNS
+        ))?;
+
+        stmt.query_row(params![site_id, post_id], post_from_row)
+            .optional()
+    }
 
-        stmt.query_row(params![site_id, post_id], |row| {
-            Ok(Post {
-                title: row.get(0)?,
-                image: row.get(1)?,
-                date: row.get(2)?,
-                content: row.get(3)?,
-                tags: vec![],            // Load from tags table in future
-                filename: String::new(), // Generate from title when needed
-                post_id: row.get(4)?,
-                site_id: row.get(5)?,
-                excerpt: row.get(6)?,
-            })
-        })
-        .optional()
-    }
-
-    pub fn update(&self, post: &Post, site_id: &str) -> Result<()> {
+    /// Updates a post. An empty post.slug keeps the current slug, unless the title changed,
+    /// then a new one is made from the title
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The site's Markdown extensions, the post's statistics are counted with them
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the slug the post was saved with
+    pub fn update(&self, post: &Post, site_id: &str, options: &MarkdownOptions) -> Result<String> {
         println!("Updating post: {} for site {}", post.post_id, site_id);
+        let current: Option<(String, Option<String>)> = self
+            .conn
+            .query_row(
+                "SELECT title, slug FROM posts WHERE site_id = ?1 and post_id = ?2",
+                params![site_id, post.post_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        let (current_title, current_slug) = current.unwrap_or_default();
+        let wanted = match &current_slug {
+            _ if !post.slug.is_empty() => post.slug.clone(),
+            Some(slug) if current_title == post.title => slug.clone(),
+            _ => String::new(),
+        };
+        let slug = unique_slug(
+            &self.conn,
+            site_id,
+            &slug_base(&wanted, &post.title),
+            post.post_id,
+        )?;
+
+        self.conn.execute(
+            "UPDATE posts SET title = ?1, header_image = ?2, content = ?3, tags = ?4, slug = ?5,
+             excerpt = ?6 WHERE site_id = ?7 and post_id = ?8",
+            params![
+                post.title,
+                post.image,
+                post.content,
+                post.tags.join(","),
+                slug,
+                post.excerpt,
+                site_id,
+                post.post_id
+            ],
+        )?;
+        self.record_slug_change(site_id, post.post_id, current_slug.as_deref(), &slug)?;
+        save_stats(&self.conn, post.post_id, &post.content, options)?;
+        Ok(slug)
+    }
+
+    /// Whether a WordPress post was already imported into a site, found by its WordPress ID
+    pub fn is_imported(&self, site_id: &str, wordpress_id: &str) -> Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM posts WHERE site_id = ?1
+               AND ?2 != '' AND wordpress_id = ?2)",
+            params![site_id, wordpress_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Remembers which WordPress post a post was imported from, so importing it again skips it
+    pub fn set_wordpress_id(&self, site_id: &str, slug: &str, wordpress_id: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE posts SET wordpress_id = ?1 WHERE site_id = ?2 AND slug = ?3",
+            params![wordpress_id, site_id, slug],
+        )?;
+        Ok(())
+    }
+
+    /// Adds up the content statistics of every post on a site
+    pub fn site_stats(&self, site_id: &str) -> Result<SiteStats> {
+        self.conn.query_row(
+            "SELECT COUNT(*), TOTAL(word_count), MAX(word_count), TOTAL(reading_time),
+                    TOTAL(heading_count), TOTAL(image_count), TOTAL(link_count)
+             FROM posts WHERE site_id = ?1",
+            params![site_id],
+            |row| {
+                let post_count: u32 = row.get(0)?;
+                let word_count = row.get::<_, f64>(1)? as u32;
+                Ok(SiteStats {
+                    post_count,
+                    word_count,
+                    average_word_count: word_count.checked_div(post_count).unwrap_or(0),
+                    longest_word_count: row.get::<_, Option<u32>>(2)?.unwrap_or(0),
+                    reading_time: row.get::<_, f64>(3)? as u32,
+                    heading_count: row.get::<_, f64>(4)? as u32,
+                    image_count: row.get::<_, f64>(5)? as u32,
+                    link_count: row.get::<_, f64>(6)? as u32,
+                })
+            },
+        )
+    }
+
+    /// Keeps slug_history up to date when a post gets a slug, the old slug is remembered so
+    /// it can redirect, and the new one stops being a redirect if it was one
+    fn record_slug_change(
+        &self,
+        site_id: &str,
+        post_id: u64,
+        old: Option<&str>,
+        new: &str,
+    ) -> Result<()> {
         self.conn.execute(
-            "UPDATE posts SET title = ?1, header_image = ?2, content = ?3
-             WHERE site_id = ?4 and post_id = ?5",
-            params![post.title, post.image, post.content, site_id, post.post_id],
+            "DELETE FROM slug_history WHERE site_id = ?1 AND slug = ?2",
+            params![site_id, new],
         )?;
+        if let Some(old) = old.filter(|old| !old.eq_ignore_ascii_case(new)) {
+            self.conn.execute(
+                "INSERT INTO slug_history (site_id, post_id, slug) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(site_id, slug) DO UPDATE SET post_id = excluded.post_id",
+                params![site_id, post_id, old],
+            )?;
+        }
         Ok(())
     }
 
     pub fn delete(&self, site_id: &str, post_id: u64) -> Result<()> {
+        // nothing left to redirect to
+        self.conn.execute(
+            "DELETE FROM slug_history WHERE site_id = ?1 and post_id = ?2",
+            params![site_id, post_id],
+        )?;
         self.conn.execute(
             "DELETE FROM posts WHERE site_id = ?1 and post_id = ?2",
             params![site_id, post_id],
@@ -61,23 +230,12 @@ impl PostRepository {
     }
 
     pub fn list_all(&self, site_id: &str) -> Result<Vec<Post>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT title, header_image, date, content, post_id, site_id, excerpt FROM posts WHERE site_id = ?1"
-        )?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM posts WHERE site_id = ?1",
+            POST_COLUMNS
+        ))?;
 
-        let posts_iter = stmt.query_map(params![site_id], |row| {
-            Ok(Post {
-                title: row.get(0)?,
-                image: row.get(1)?,
-                date: row.get(2)?,
-                content: row.get(3)?,
-                tags: vec![],            // Load from tags table in future
-                filename: String::new(), // Generate from title when needed
-                post_id: row.get(4)?, // post Id and site Id don't matter here, won't be getting this
-                site_id: row.get(5)?, // shit from disk anymore
-                excerpt: row.get(6)?,
-            })
-        })?;
+        let posts_iter = stmt.query_map(params![site_id], post_from_row)?;
 
         let mut posts = Vec::new();
         for post in posts_iter {
@@ -88,27 +246,16 @@ impl PostRepository {
     }
 
     pub fn get_recent_posts(&self, site_id: &str, limit: i32) -> Result<Vec<Post>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT title, date, content, header_image, post_id, site_id, excerpt
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}
              FROM posts
              WHERE site_id = ?1
              ORDER BY date DESC
              LIMIT ?2",
-        )?;
+            POST_COLUMNS
+        ))?;
 
-        let posts = stmt.query_map(params![site_id, limit], |row| {
-            Ok(Post {
-                title: row.get(0)?,
-                date: row.get(1)?,
-                content: row.get(2)?,
-                image: row.get(3)?,
-                tags: vec![], // Tags would need a separate table/query
-                filename: String::new(),
-                post_id: row.get(4)?, // post Id and site Id don't matter here, won't be getting this
-                site_id: row.get(5)?, // shit from disk anymore
-                excerpt: row.get(6)?,
-            })
-        })?;
+        let posts = stmt.query_map(params![site_id, limit], post_from_row)?;
 
         let mut results = Vec::new();
         for post in posts {
@@ -118,6 +265,49 @@ impl PostRepository {
         Ok(results)
     }
 
+    /// Searches post titles, content, excerpts and tags, best matches first.
+    /// Words are matched in any order and form, "deploying" finds "deploy", and the last word
+    /// can be unfinished
+    ///
+    /// # Arguments
+    ///
+    /// * `site_id` - The site to search, None searches every site
+    /// * `query` - What was typed into the search box
+    pub fn search(&self, site_id: Option<&str>, query: &str) -> Result<Vec<SearchResult>> {
+        let query = match fts_query(query) {
+            Some(query) => query,
+            None => return Ok(Vec::new()),
+        };
+
+        // title matches count the most, then excerpt and tags, then content
+        let mut stmt = self.conn.prepare(
+            "SELECT p.post_id, p.site_id, s.name, p.title, p.slug,
+                    snippet(posts_fts, 1, ?3, ?4, '…', 16),
+                    bm25(posts_fts, 10.0, 1.0, 4.0, 4.0) AS rank
+             FROM posts_fts
+             JOIN posts p ON p.post_id = posts_fts.rowid
+             LEFT JOIN sites s ON s.id = p.site_id
+             WHERE posts_fts MATCH ?1 AND (?2 IS NULL OR p.site_id = ?2)
+             ORDER BY rank
+             LIMIT ?5",
+        )?;
+        let results = stmt.query_map(
+            params![query, site_id, MATCH_START, MATCH_END, SEARCH_LIMIT],
+            |row| {
+                Ok(SearchResult {
+                    post_id: row.get(0)?,
+                    site_id: row.get(1)?,
+                    site_name: row.get(2)?,
+                    title: row.get(3)?,
+                    slug: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                    snippet: mark_matches(&row.get::<_, String>(5)?),
+                    rank: row.get(6)?,
+                })
+            },
+        )?;
+        results.collect()
+    }
+
     pub fn get_post_count(&self, site_id: &str) -> Result<i64> {
 
         let mut stmt = self.conn.prepare(
@@ -136,3 +326,321 @@ impl PostRepository {
         Ok(result)
     }
 }
+
+/// The slug to start from when saving a post, a valid wanted slug is kept as is,
+/// anything else is slugged and an empty one falls back to the title
+fn slug_base(wanted: &str, title: &str) -> String {
+    if is_valid_slug(wanted) {
+        return wanted.to_string();
+    }
+    let slug = slugify(if wanted.trim().is_empty() { title } else { wanted });
+    if slug.is_empty() {
+        FALLBACK_SLUG.to_string()
+    } else {
+        slug
+    }
+}
+
+/// Finds a slug no other post on the site uses or used to use,
+/// adding -2, -3... to base until one is free. Old slugs stay reserved so their redirects keep working.
+/// Compared without case, so two posts never map to the same file on case-insensitive disks
+///
+/// # Arguments
+///
+/// * `conn` - Connection to the DB
+/// * `site_id` - The site the slug has to be unique in
+/// * `base` - The slug to start from
+/// * `post_id` - The post the slug is for, its own slug doesn't count as taken. 0 for new posts
+pub fn unique_slug(conn: &Connection, site_id: &str, base: &str, post_id: u64) -> Result<String> {
+    let mut stmt = conn.prepare(
+        "SELECT
+           (SELECT COUNT(*) FROM posts
+            WHERE site_id = ?1 AND post_id != ?2 AND slug = ?3 COLLATE NOCASE)
+         + (SELECT COUNT(*) FROM slug_history
+            WHERE site_id = ?1 AND post_id != ?2 AND slug = ?3 COLLATE NOCASE)",
+    )?;
+
+    let mut slug = base.to_string();
+    let mut suffix = 1;
+    while stmt.query_row(params![site_id, post_id, slug], |row| row.get::<_, i64>(0))? > 0 {
+        suffix += 1;
+        slug = format!("{}-{}", base, suffix);
+    }
+    Ok(slug)
+}
+
+/// Counts a post's content statistics and stores them with it
+///
+/// # Arguments
+///
+/// * `conn` - Connection to the DB
+/// * `post_id` - The post to store them on
+/// * `content` - The post's Markdown
+/// * `options` - The site's Markdown extensions, so tables and footnotes count like the build sees them
+pub fn save_stats(
+    conn: &Connection,
+    post_id: u64,
+    content: &str,
+    options: &MarkdownOptions,
+) -> Result<PostStats> {
+    let stats = PostStats::from_markdown(content, options);
+    conn.execute(
+        "UPDATE posts SET word_count = ?1, reading_time = ?2, heading_count = ?3,
+         image_count = ?4, link_count = ?5 WHERE post_id = ?6",
+        params![
+            stats.word_count,
+            stats.reading_time,
+            stats.heading_count,
+            stats.image_count,
+            stats.link_count,
+            post_id
+        ],
+    )?;
+    Ok(stats)
+}
+
+/// Turns what was typed into an FTS5 query. Each word is quoted so punctuation and FTS5's own
+/// syntax are searched for as text, every word has to match and the last one matches as a prefix
+fn fts_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    Some(format!("{}*", words.join(" ")))
+}
+
+/// Escapes a snippet and swaps the match markers for <mark> tags
+fn mark_matches(snippet: &str) -> String {
+    snippet
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+/// Builds a Post from a row selected with POST_COLUMNS
+fn post_from_row(row: &Row) -> Result<Post> {
+    let tags: Option<String> = row.get(7)?;
+    Ok(Post {
+        title: row.get(0)?,
+        slug: row.get::<_, Option<String>>(8)?.unwrap_or_default(),
+        image: row.get(1)?,
+        date: row.get(2)?,
+        content: row.get(3)?,
+        tags: tags
+            .unwrap_or_default()
+            .split(',')
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+        filename: String::new(), // Generate from title when needed
+        post_id: row.get(4)?,
+        site_id: row.get(5)?,
+        excerpt: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+        stats: PostStats {
+            word_count: row.get::<_, Option<u32>>(9)?.unwrap_or_default(),
+            reading_time: row.get::<_, Option<u32>>(10)?.unwrap_or_default(),
+            heading_count: row.get::<_, Option<u32>>(11)?.unwrap_or_default(),
+            image_count: row.get::<_, Option<u32>>(12)?.unwrap_or_default(),
+            link_count: row.get::<_, Option<u32>>(13)?.unwrap_or_default(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugs_are_unique_per_site() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute(
+            "CREATE TABLE posts (site_id TEXT, post_id INTEGER PRIMARY KEY, title TEXT, slug TEXT)",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE slug_history (site_id TEXT, post_id INTEGER, slug TEXT COLLATE NOCASE)",
+            [],
+        )?;
+        conn.execute(
+            "INSERT INTO posts (site_id, post_id, title, slug) VALUES
+             ('a', 1, 'Hello', 'Hello-World'), ('a', 2, 'Hi', 'hello-world-2'), ('b', 3, 'Hey', 'hey')",
+            [],
+        )?;
+        conn.execute(
+            "INSERT INTO slug_history (site_id, post_id, slug) VALUES ('a', 2, 'hello-world-3')",
+            [],
+        )?;
+
+        assert_eq!(slug_base("", "What's new in v2.0?"), "whats-new-in-v2-0");
+        assert_eq!(slug_base("", "Café über Straße"), "café-über-straße");
+        assert_eq!(slug_base("Hello-World", "Renamed"), "Hello-World");
+        assert_eq!(slug_base("My Custom Slug", "Renamed"), "my-custom-slug");
+        assert_eq!(slug_base("", "???"), FALLBACK_SLUG);
+
+        // taken without regard to case, skipping suffixes that are taken too,
+        // and slugs other posts used to have
+        assert_eq!(unique_slug(&conn, "a", "hello-world", 0)?, "hello-world-4");
+        assert_eq!(unique_slug(&conn, "a", "hello-world-3", 2)?, "hello-world-3");
+        // a post keeps its own slug
+        assert_eq!(unique_slug(&conn, "a", "Hello-World", 1)?, "Hello-World");
+        // other sites don't count
+        assert_eq!(unique_slug(&conn, "b", "hello-world", 0)?, "hello-world");
+        Ok(())
+    }
+
+    /// A repository on an in-memory DB with the tables posts need
+    fn test_repository() -> Result<PostRepository> {
+        let post_repo = PostRepository {
+            conn: Connection::open_in_memory()?,
+        };
+        post_repo.conn.execute_batch(
+            "CREATE TABLE sites (id TEXT PRIMARY KEY, name TEXT);
+             CREATE TABLE posts (site_id TEXT, post_id INTEGER PRIMARY KEY, title TEXT,
+               header_image TEXT, date TEXT, content TEXT, excerpt TEXT, tags TEXT, slug TEXT,
+               wordpress_id TEXT, word_count INTEGER, reading_time INTEGER, heading_count INTEGER,
+               image_count INTEGER, link_count INTEGER);
+             CREATE TABLE slug_history (site_id TEXT, post_id INTEGER, slug TEXT COLLATE NOCASE);",
+        )?;
+        crate::db::create_search_index(&post_repo.conn)?;
+        Ok(post_repo)
+    }
+
+    #[test]
+    fn test_stats_are_saved_and_added_up() -> Result<()> {
+        let post_repo = test_repository()?;
+        let options = MarkdownOptions::default();
+        assert_eq!(post_repo.site_stats("a")?, SiteStats::default());
+
+        let mut post = Post::new(String::from("First"));
+        post.content = String::from("## One\n\nThree short words [here](https://example.com).");
+        post_repo.create(&post, "a", &options)?;
+        let mut other = Post::new(String::from("Second"));
+        other.content = "word ".repeat(250);
+        post_repo.create(&other, "a", &options)?;
+        post_repo.create(&other, "b", &options)?;
+
+        let saved = post_repo.read("a", 1)?.unwrap();
+        assert_eq!(
+            saved.stats,
+            PostStats {
+                word_count: 5,
+                reading_time: 1,
+                heading_count: 1,
+                image_count: 0,
+                link_count: 1,
+            }
+        );
+
+        // editing the post counts it again
+        post.post_id = 1;
+        post.content = String::from("Shorter.");
+        post_repo.update(&post, "a", &options)?;
+        assert_eq!(post_repo.read("a", 1)?.unwrap().stats.word_count, 1);
+
+        assert_eq!(
+            post_repo.site_stats("a")?,
+            SiteStats {
+                post_count: 2,
+                word_count: 251,
+                average_word_count: 125,
+                longest_word_count: 250,
+                reading_time: 3,
+                heading_count: 0,
+                image_count: 0,
+                link_count: 0,
+            }
+        );
+
+        // counted with the site's own extensions, a {#id} is only hidden when heading IDs are on
+        let mut heading = Post::new(String::from("Heading"));
+        heading.content = String::from("# Hello {#intro}");
+        post_repo.create(&heading, "c", &options)?;
+        let no_ids = MarkdownOptions {
+            heading_ids: false,
+            ..options
+        };
+        post_repo.create(&heading, "d", &no_ids)?;
+        assert_eq!(post_repo.site_stats("c")?.word_count, 1);
+        assert_eq!(post_repo.site_stats("d")?.word_count, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wordpress_imports_are_remembered() -> Result<()> {
+        let post_repo = test_repository()?;
+        let options = MarkdownOptions::default();
+        let slug = post_repo.create(&Post::new(String::from("Imported")), "a", &options)?;
+        post_repo.set_wordpress_id("a", &slug, "42")?;
+
+        assert!(post_repo.is_imported("a", "42")?);
+        assert!(!post_repo.is_imported("a", "7")?);
+        assert!(!post_repo.is_imported("b", "42")?);
+        // posts written in Driftwood have no WordPress ID
+        assert!(!post_repo.is_imported("a", "")?);
+
+        // another WordPress post with the same slug isn't skipped, it gets a slug of its own
+        let mut other = Post::new(String::from("Other"));
+        other.slug = slug.clone();
+        assert!(!post_repo.is_imported("a", "7")?);
+        let other_slug = post_repo.create(&other, "a", &options)?;
+        assert_eq!(other_slug, format!("{}-2", slug));
+        post_repo.set_wordpress_id("a", &other_slug, "7")?;
+        assert!(post_repo.is_imported("a", "7")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_search() -> Result<()> {
+        let post_repo = test_repository()?;
+        let options = MarkdownOptions::default();
+        post_repo
+            .conn
+            .execute("INSERT INTO sites (id, name) VALUES ('a', 'Site A')", [])?;
+
+        let mut deploying = Post::new(String::from("Deploying to Netlify"));
+        deploying.content = String::from("How we deploy <b>static</b> sites from the app.");
+        deploying.tags = vec![String::from("hosting")];
+        post_repo.create(&deploying, "a", &options)?;
+        let mut mention = Post::new(String::from("Release notes"));
+        mention.content = String::from("Fixed a bug when deploying sites.");
+        post_repo.create(&mention, "b", &options)?;
+        let mut unrelated = Post::new(String::from("Cooking"));
+        unrelated.content = String::from("Nothing to see here.");
+        post_repo.create(&unrelated, "a", &options)?;
+
+        // stemmed, so "deploy" finds "deploying", and title matches rank first
+        let results = post_repo.search(None, "deploy")?;
+        let titles: Vec<&str> = results.iter().map(|result| result.title.as_str()).collect();
+        assert_eq!(titles, vec!["Deploying to Netlify", "Release notes"]);
+        assert_eq!(results[0].site_name.as_deref(), Some("Site A"));
+        assert_eq!(results[0].slug, "deploying-to-netlify");
+        assert_eq!(
+            results[0].snippet,
+            "How we <mark>deploy</mark> &lt;b&gt;static&lt;/b&gt; sites from the app."
+        );
+
+        // one site, tags, unfinished words, and text FTS5 would read as syntax
+        assert_eq!(post_repo.search(Some("b"), "deploy")?.len(), 1);
+        assert_eq!(post_repo.search(Some("a"), "host")?.len(), 1);
+        assert_eq!(post_repo.search(None, "Netl")?.len(), 1);
+        assert!(post_repo.search(None, "\"AND OR* -:(")?.is_empty());
+        assert!(post_repo.search(None, "   ")?.is_empty());
+
+        // the index follows edits and deletes
+        let mut edited = unrelated.clone();
+        edited.post_id = 3;
+        edited.content = String::from("Deploy dinner.");
+        post_repo.update(&edited, "a", &options)?;
+        assert_eq!(post_repo.search(Some("a"), "deploy")?.len(), 2);
+        post_repo.delete("a", 1)?;
+        assert_eq!(post_repo.search(Some("a"), "deploy")?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,381 @@
+/// Local preview server, serves a site's build on localhost and reloads open tabs when it changes
+use crate::builder::content_type_for;
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Path of the server-sent events stream the injected script listens on
+const LIVE_RELOAD_PATH: &str = "/__driftwood/livereload";
+
+/// Injected before </body> in every HTML page the preview serves
+const LIVE_RELOAD_SCRIPT: &str = "<script>new EventSource('/__driftwood/livereload').onmessage = () => location.reload();</script>";
+
+/// How often watched files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Renders the site into the given directory
+pub type Rebuild = Box<dyn Fn(&Path) -> Result<()> + Send + Sync>;
+
+/// State shared between the server thread and the connections it spawns
+struct Shared {
+    dir: PathBuf,
+    rebuild: Rebuild,
+    // bumped on every rebuild, open pages reload when it changes
+    version: AtomicU64,
+    // set when the site changed, the server thread rebuilds so callers don't wait on the build
+    dirty: AtomicBool,
+    shutdown: AtomicBool,
+    build_lock: Mutex<()>,
+}
+
+impl Shared {
+    fn refresh(&self) -> Result<()> {
+        let _guard = self.build_lock.lock().unwrap();
+        (self.rebuild)(&self.dir)?;
+        self.version.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// PreviewServer struct
+/// A running preview, stopped and cleaned up with stop()
+pub struct PreviewServer {
+    pub port: u16,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PreviewServer {
+    /// Builds the site into dir and starts serving it on a free port
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory the site is built into and served from, removed when the server stops
+    /// * `rebuild` - Renders the site into dir, called now and whenever something changes
+    /// * `watch` - Files and directories that trigger a rebuild when they're modified
+    pub fn start(dir: PathBuf, rebuild: Rebuild, watch: Vec<PathBuf>) -> Result<PreviewServer> {
+        let shared = Arc::new(Shared {
+            dir,
+            rebuild,
+            version: AtomicU64::new(0),
+            dirty: AtomicBool::new(false),
+            shutdown: AtomicBool::new(false),
+            build_lock: Mutex::new(()),
+        });
+        shared.refresh().context("Failed to build the preview")?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        println!("> Preview server listening on port {}", port);
+
+        let server_shared = shared.clone();
+        let thread = thread::spawn(move || serve(listener, server_shared, watch));
+
+        Ok(PreviewServer {
+            port,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.port)
+    }
+
+    /// Rebuilds the site and tells open pages to reload
+    pub fn refresh(&self) -> Result<()> {
+        self.shared.refresh()
+    }
+
+    /// Asks the server thread to rebuild the site, returning straight away
+    pub fn mark_changed(&self) {
+        self.shared.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops the server and removes the preview build
+    pub fn stop(mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = fs::remove_dir_all(&self.shared.dir);
+        println!("> Preview server on port {} stopped", self.port);
+    }
+}
+
+/// Running previews by site ID
+fn previews() -> &'static Mutex<HashMap<String, PreviewServer>> {
+    static PREVIEWS: OnceLock<Mutex<HashMap<String, PreviewServer>>> = OnceLock::new();
+    PREVIEWS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Starts a preview for a site, or returns the running one
+///
+/// # Returns
+///
+/// A Result containing the URL of the preview
+pub fn start_site_preview(site_id: &str, rebuild: Rebuild, watch: Vec<PathBuf>) -> Result<String> {
+    let mut previews = previews().lock().unwrap();
+    if let Some(server) = previews.get(site_id) {
+        server.mark_changed();
+        return Ok(server.url());
+    }
+
+    let dir = std::env::temp_dir().join(format!("driftwood-preview-{}", site_id));
+    let server = PreviewServer::start(dir, rebuild, watch)?;
+    let url = server.url();
+    previews.insert(site_id.to_string(), server);
+    Ok(url)
+}
+
+/// Stops a site's preview
+///
+/// # Returns
+///
+/// false if the site had no preview running
+pub fn stop_site_preview(site_id: &str) -> bool {
+    let server = previews().lock().unwrap().remove(site_id);
+    match server {
+        Some(server) => {
+            server.stop();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Rebuilds a site's preview after its posts changed, does nothing when no preview is running.
+/// The rebuild happens on the preview's own thread, saving a post doesn't wait for it
+pub fn notify_site_changed(site_id: &str) {
+    if let Some(server) = previews().lock().unwrap().get(site_id) {
+        server.mark_changed();
+    }
+}
+
+/// Accepts connections until shutdown, rebuilding when watched files change
+fn serve(listener: TcpListener, shared: Arc<Shared>, watch: Vec<PathBuf>) {
+    let mut last_change = latest_change(&watch);
+    let mut last_check = Instant::now();
+
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &shared) {
+                        println!("> Preview request failed: {}", e);
+                    }
+                });
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => println!("> Preview server error: {}", e),
+        }
+
+        if shared.dirty.swap(false, Ordering::SeqCst) {
+            println!("> Site changed, rebuilding preview");
+            if let Err(e) = shared.refresh() {
+                println!("> Failed to rebuild preview: {}", e);
+            }
+        }
+
+        if last_check.elapsed() >= WATCH_INTERVAL {
+            last_check = Instant::now();
+            let change = latest_change(&watch);
+            if change > last_change {
+                last_change = change;
+                println!("> Watched files changed, rebuilding preview");
+                if let Err(e) = shared.refresh() {
+                    println!("> Failed to rebuild preview: {}", e);
+                }
+            }
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared) -> Result<()> {
+    // accepted sockets inherit non-blocking mode on some platforms
+    stream.set_nonblocking(false)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let path = target.split(['?', '#']).next().unwrap_or("/");
+    let path = urlencoding::decode(path)?.to_string();
+
+    if path == LIVE_RELOAD_PATH {
+        return stream_reloads(stream, shared);
+    }
+
+    match resolve_file(&shared.dir, &path) {
+        Some(file) => {
+            let mut body = fs::read(&file)?;
+            let file_name = file.to_string_lossy().to_string();
+            let content_type = content_type_for(&file_name);
+            if content_type.starts_with("text/html") {
+                body = inject_live_reload(&String::from_utf8_lossy(&body)).into_bytes();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+                content_type,
+                body.len()
+            )?;
+            stream.write_all(&body)?;
+        }
+        None => {
+            let body = "Not found";
+            write!(
+                stream,
+                "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )?;
+        }
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+/// Holds the connection open and sends a reload event once the site is rebuilt
+fn stream_reloads(mut stream: TcpStream, shared: &Shared) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let seen = shared.version.load(Ordering::SeqCst);
+    let mut last_ping = Instant::now();
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        if shared.version.load(Ordering::SeqCst) != seen {
+            stream.write_all(b"data: reload\n\n")?;
+            stream.flush()?;
+            break;
+        }
+        // writing to a closed tab fails, which ends this loop
+        if last_ping.elapsed() >= Duration::from_secs(15) {
+            stream.write_all(b": ping\n\n")?;
+            last_ping = Instant::now();
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    Ok(())
+}
+
+/// Maps a request path onto a file in the build, directories serve their index.html
+fn resolve_file(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    // never serve anything outside the build
+    if relative
+        .components()
+        .any(|part| !matches!(part, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    let mut file = root.join(relative);
+    if file.is_dir() {
+        file = file.join("index.html");
+    }
+    file.is_file().then_some(file)
+}
+
+fn inject_live_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(index) => format!("{}{}{}", &html[..index], LIVE_RELOAD_SCRIPT, &html[index..]),
+        None => format!("{}{}", html, LIVE_RELOAD_SCRIPT),
+    }
+}
+
+/// The most recent modification time of anything under the given paths
+fn latest_change(paths: &[PathBuf]) -> SystemTime {
+    let mut latest = SystemTime::UNIX_EPOCH;
+    let mut pending: Vec<PathBuf> = paths.to_vec();
+
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if let Ok(modified) = metadata.modified() {
+            latest = latest.max(modified);
+        }
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()));
+            }
+        }
+    }
+    latest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_preview_serves_and_reloads() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let build_dir = dir.path().join("preview");
+        let builds = Arc::new(AtomicU64::new(0));
+
+        let counter = builds.clone();
+        let rebuild: Rebuild = Box::new(move |dir: &Path| {
+            let build = counter.fetch_add(1, Ordering::SeqCst);
+            fs::create_dir_all(dir)?;
+            fs::write(
+                dir.join("index.html"),
+                format!("<html><body>build {}</body></html>", build),
+            )?;
+            Ok(())
+        });
+        let server = PreviewServer::start(build_dir.clone(), rebuild, vec![])?;
+
+        let index = reqwest::blocking::get(server.url())?.text()?;
+        assert!(index.contains("build 0"));
+        assert!(index.contains(LIVE_RELOAD_SCRIPT));
+
+        let escape = reqwest::blocking::get(format!("{}..%2F..%2Fetc%2Fpasswd", server.url()))?;
+        assert_eq!(escape.status(), 404);
+
+        // an open page hears about the next rebuild
+        let mut events = TcpStream::connect(("127.0.0.1", server.port))?;
+        write!(events, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", LIVE_RELOAD_PATH)?;
+        thread::sleep(Duration::from_millis(300));
+        server.mark_changed();
+        let mut received = String::new();
+        events.set_read_timeout(Some(Duration::from_secs(5)))?;
+        events.read_to_string(&mut received)?;
+        assert!(received.contains("data: reload"));
+
+        let index = reqwest::blocking::get(server.url())?.text()?;
+        assert!(index.contains("build 1"));
+
+        server.stop();
+        assert!(!build_dir.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+/// Redirect and rewrite rules for a site, written to a Netlify `_redirects` file when it's built
+use crate::db::DB_PATH;
+use rusqlite::{params, Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Status codes a rule can use, 200 makes it a rewrite instead of a redirect
+const ALLOWED_STATUSES: [u16; 9] = [200, 301, 302, 303, 307, 308, 404, 410, 451];
+
+/// RedirectRule struct
+/// One line of the `_redirects` file
+/// id: Row ID for custom rules, 0 for rules generated from slug history
+/// from: The path being requested, e.g. /old-blog/*
+/// to: Where it goes, a path on the site or a full URL, e.g. /posts/:splat
+/// status: 301 by default, 200 serves `to` without changing the URL (a rewrite)
+/// force: Apply the rule even when a file exists at `from`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct RedirectRule {
+    #[serde(default)]
+    pub id: u64,
+    pub from: String,
+    pub to: String,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub force: bool,
+}
+
+fn default_status() -> u16 {
+    301
+}
+
+impl RedirectRule {
+    /// A permanent redirect from one path to another
+    pub fn moved(from: String, to: String) -> RedirectRule {
+        RedirectRule {
+            id: 0,
+            from,
+            to,
+            status: 301,
+            force: false,
+        }
+    }
+
+    /// Checks the rule can be written to `_redirects` and that Netlify will accept it
+    ///
+    /// # Returns
+    ///
+    /// A Result with a message describing what's wrong with the rule
+    pub fn validate(&self) -> Result<(), String> {
+        let has_space = |value: &str| value.chars().any(char::is_whitespace);
+
+        if !self.from.starts_with('/') {
+            return Err(format!("'{}' must be a path starting with /", self.from));
+        }
+        if has_space(&self.from) || has_space(&self.to) {
+            return Err(String::from("Paths can't contain spaces"));
+        }
+        if !(self.to.starts_with('/')
+            || self.to.starts_with("https://")
+            || self.to.starts_with("http://"))
+        {
+            return Err(format!(
+                "'{}' must be a path starting with / or a full http(s) URL",
+                self.to
+            ));
+        }
+        if self.from == self.to {
+            return Err(format!("'{}' redirects to itself", self.from));
+        }
+        if !ALLOWED_STATUSES.contains(&self.status) {
+            return Err(format!("{} is not a supported status code", self.status));
+        }
+        Ok(())
+    }
+
+    /// The rule as a `_redirects` line, e.g. `/old /new 301!`
+    pub fn to_line(&self) -> String {
+        format!(
+            "{} {} {}{}",
+            self.from,
+            self.to,
+            self.status,
+            if self.force { "!" } else { "" }
+        )
+    }
+}
+
+/// Builds the contents of a `_redirects` file, Netlify uses the first rule that matches
+pub fn redirects_file(rules: &[RedirectRule]) -> String {
+    let mut file = String::from("# Generated by Driftwood, edit redirects in the app\n");
+    for rule in rules {
+        file.push_str(&rule.to_line());
+        file.push('\n');
+    }
+    file
+}
+
+pub struct RedirectRepository {
+    conn: Connection,
+}
+
+impl RedirectRepository {
+    pub fn new() -> Result<Self> {
+        let conn = Connection::open(Path::new(DB_PATH))?;
+        Ok(Self { conn })
+    }
+
+    /// Adds a custom rule to the end of the site's list
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new rule's ID
+    pub fn create(&self, site_id: &str, rule: &RedirectRule) -> Result<u64> {
+        self.conn.execute(
+            "INSERT INTO redirects (site_id, from_path, to_path, status, force)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![site_id, rule.from, rule.to, rule.status, rule.force],
+        )?;
+        Ok(self.conn.last_insert_rowid() as u64)
+    }
+
+    pub fn delete(&self, site_id: &str, id: u64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM redirects WHERE site_id = ?1 AND id = ?2",
+            params![site_id, id],
+        )?;
+        Ok(())
+    }
+
+    /// The site's custom rules, in the order they were added
+    pub fn list(&self, site_id: &str) -> Result<Vec<RedirectRule>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, from_path, to_path, status, force
+             FROM redirects WHERE site_id = ?1 ORDER BY id",
+        )?;
+
+        let rules = stmt.query_map(params![site_id], |row| {
+            Ok(RedirectRule {
+                id: row.get(0)?,
+                from: row.get(1)?,
+                to: row.get(2)?,
+                status: row.get(3)?,
+                force: row.get::<_, Option<bool>>(4)?.unwrap_or(false),
+            })
+        })?;
+
+        rules.collect()
+    }
+
+    /// 301s from every slug a post used to have to where it lives now
+    pub fn slug_redirects(&self, site_id: &str) -> Result<Vec<RedirectRule>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT slug_history.slug, posts.slug
+             FROM slug_history JOIN posts ON posts.post_id = slug_history.post_id
+             WHERE slug_history.site_id = ?1 AND posts.slug IS NOT NULL
+             ORDER BY slug_history.slug",
+        )?;
+
+        let moves = stmt.query_map(params![site_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut rules = Vec::new();
+        for slug_move in moves {
+            let (old, new) = slug_move?;
+            // Netlify serves posts/new.html at /posts/new too, cover both forms of the old URL
+            rules.push(RedirectRule::moved(
+                format!("/posts/{}.html", old),
+                format!("/posts/{}.html", new),
+            ));
+            rules.push(RedirectRule::moved(
+                format!("/posts/{}", old),
+                format!("/posts/{}.html", new),
+            ));
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_and_render_rules() {
+        let rewrite = RedirectRule {
+            id: 1,
+            from: String::from("/blog/*"),
+            to: String::from("/posts/:splat"),
+            status: 200,
+            force: true,
+        };
+        let moved = RedirectRule::moved(String::from("/about"), String::from("https://example.com"));
+        assert!(rewrite.validate().is_ok());
+        assert!(moved.validate().is_ok());
+
+        let invalid = [
+            RedirectRule::moved(String::from("about"), String::from("/about-us")),
+            RedirectRule::moved(String::from("/a b"), String::from("/c")),
+            RedirectRule::moved(String::from("/a"), String::from("javascript:alert(1)")),
+            RedirectRule::moved(String::from("/a"), String::from("/a")),
+            RedirectRule {
+                status: 418,
+                ..RedirectRule::moved(String::from("/a"), String::from("/b"))
+            },
+        ];
+        for rule in invalid {
+            assert!(rule.validate().is_err(), "{:?} should be invalid", rule);
+        }
+
+        assert_eq!(
+            redirects_file(&[rewrite, moved]),
+            "# Generated by Driftwood, edit redirects in the app\n\
+             /blog/* /posts/:splat 200!\n\
+             /about https://example.com 301\n"
+        );
+    }
+}
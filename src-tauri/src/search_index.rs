@@ -0,0 +1,224 @@
+/// The search index deployed with a site, a JSON file themes load so readers can search every
+/// post without a server. Words are stemmed, so "deploying" and "deployed" are both found under "deploy",
+/// and kept as written too, so a word typed in full is found even when its stem doesn't start with it
+use crate::driftwood::MarkdownOptions;
+use anyhow::{Context, Result};
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::Serialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// The index's file name, at the root of the build
+pub const SEARCH_INDEX_FILE: &str = "search-index.json";
+
+/// Bumped when the index's layout changes, so themes can tell what they're reading
+const SEARCH_INDEX_VERSION: u32 = 1;
+
+/// How much a word counts for where it's found, a match in the title beats one in the text
+const TITLE_WEIGHT: u32 = 8;
+const TAG_WEIGHT: u32 = 4;
+const EXCERPT_WEIGHT: u32 = 2;
+const TEXT_WEIGHT: u32 = 1;
+
+/// Words too common to be worth searching for
+const STOP_WORDS: [&str; 32] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "has", "have", "i",
+    "if", "in", "into", "is", "it", "its", "not", "of", "on", "or", "so", "that", "the", "this",
+    "to", "was", "were", "with",
+];
+
+/// SearchDocument struct
+/// A post as it goes into the index
+/// url: Relative to the site root, e.g. posts/hello.html
+/// text: The post's text without markup, see plain_text
+#[derive(Debug, Clone, Default)]
+pub struct SearchDocument {
+    pub title: String,
+    pub url: String,
+    pub date: String,
+    pub excerpt: String,
+    pub tags: Vec<String>,
+    pub text: String,
+}
+
+/// SearchIndex struct
+/// What's written to search-index.json
+/// posts: What a result shows, in the order posts are listed on the site
+/// terms: Each stemmed word, and each word as written when that differs from its stem, with the
+///   posts it's in as [post, weight, post, weight, ...] where post is an index into posts
+#[derive(Debug, Serialize)]
+pub struct SearchIndex {
+    pub version: u32,
+    pub posts: Vec<IndexedPost>,
+    pub terms: BTreeMap<String, Vec<u32>>,
+}
+
+/// IndexedPost struct
+/// A post in the search index, without its text
+#[derive(Debug, Serialize, PartialEq)]
+pub struct IndexedPost {
+    pub title: String,
+    pub url: String,
+    pub date: String,
+    pub excerpt: String,
+    pub tags: Vec<String>,
+}
+
+impl SearchIndex {
+    /// Indexes the posts, each word weighted by where it's found and how often
+    pub fn build(documents: &[SearchDocument]) -> SearchIndex {
+        let stemmer = Stemmer::create(Algorithm::English);
+        let mut terms: BTreeMap<String, BTreeMap<u32, u32>> = BTreeMap::new();
+
+        for (post, document) in documents.iter().enumerate() {
+            let fields = [
+                (document.title.as_str(), TITLE_WEIGHT),
+                (document.excerpt.as_str(), EXCERPT_WEIGHT),
+                (document.text.as_str(), TEXT_WEIGHT),
+            ]
+            .into_iter()
+            .chain(document.tags.iter().map(|tag| (tag.as_str(), TAG_WEIGHT)));
+
+            for (text, weight) in fields {
+                for term in index_terms(&stemmer, text) {
+                    *terms
+                        .entry(term)
+                        .or_default()
+                        .entry(post as u32)
+                        .or_default() += weight;
+                }
+            }
+        }
+
+        SearchIndex {
+            version: SEARCH_INDEX_VERSION,
+            posts: documents
+                .iter()
+                .map(|document| IndexedPost {
+                    title: document.title.clone(),
+                    url: document.url.clone(),
+                    date: document.date.clone(),
+                    excerpt: document.excerpt.clone(),
+                    tags: document.tags.clone(),
+                })
+                .collect(),
+            terms: terms
+                .into_iter()
+                .map(|(term, posts)| {
+                    let flat = posts
+                        .into_iter()
+                        .flat_map(|(post, weight)| [post, weight])
+                        .collect();
+                    (term, flat)
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes the index into the build as SEARCH_INDEX_FILE
+    pub fn write(&self, out_dir: &Path) -> Result<()> {
+        let json = serde_json::to_string(self).context("Failed to serialize the search index")?;
+        fs::write(out_dir.join(SEARCH_INDEX_FILE), json).context("Failed to write the search index")
+    }
+}
+
+/// The words in a post's Markdown, without markup, links' URLs or code blocks
+///
+/// # Arguments
+///
+/// * `markdown` - The post's content
+/// * `options` - The site's Markdown extensions
+pub fn plain_text(markdown: &str, options: &MarkdownOptions) -> String {
+    let mut text = String::new();
+    let mut in_code_block = false;
+    for event in Parser::new_ext(markdown, options.parser_options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Text(part) | Event::Code(part) if !in_code_block => text.push_str(&part),
+            // inline formatting can sit in the middle of a word
+            Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link { .. })
+            | Event::End(
+                TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Link,
+            ) => {}
+            Event::Start(_) | Event::End(_) | Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Lowercased words of a text, each stem followed by the word itself when it's different,
+/// without stop words and single letters
+fn index_terms<'a>(stemmer: &'a Stemmer, text: &'a str) -> impl Iterator<Item = String> + 'a {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|word| word.chars().count() > 1 && !STOP_WORDS.contains(&word.as_str()))
+        .flat_map(|word| {
+            let stem = stemmer.stem(&word).into_owned();
+            match stem == word {
+                true => vec![stem],
+                false => vec![stem, word],
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_search_index() -> Result<()> {
+        let options = MarkdownOptions::default();
+        let documents = vec![
+            SearchDocument {
+                title: String::from("Deploying to Netlify"),
+                url: String::from("posts/deploying-to-netlify.html"),
+                tags: vec![String::from("Hosting")],
+                text: plain_text(
+                    "We **deploy**ed the [site](https://example.com).\n\n```\nhidden_code()\n```\n",
+                    &options,
+                ),
+                ..SearchDocument::default()
+            },
+            SearchDocument {
+                title: String::from("Cooking"),
+                url: String::from("posts/cooking.html"),
+                excerpt: String::from("Deploy dinner, the fast way."),
+                text: String::from("We ate happily."),
+                ..SearchDocument::default()
+            },
+        ];
+        assert!(documents[0].text.contains("We deployed the site"));
+
+        let index = SearchIndex::build(&documents);
+        // title, then the word in the text, stemmed to the same term
+        assert_eq!(
+            index.terms["deploy"],
+            vec![0, TITLE_WEIGHT + TEXT_WEIGHT, 1, EXCERPT_WEIGHT]
+        );
+        assert_eq!(index.terms["host"], vec![0, TAG_WEIGHT]);
+        assert_eq!(index.terms["fast"], vec![1, EXCERPT_WEIGHT]);
+        // words are kept as written too, "happily" doesn't start its stem "happili"
+        assert_eq!(index.terms["deploying"], vec![0, TITLE_WEIGHT]);
+        assert_eq!(index.terms["deployed"], vec![0, TEXT_WEIGHT]);
+        assert_eq!(index.terms["happily"], vec![1, TEXT_WEIGHT]);
+        assert_eq!(index.terms["happili"], vec![1, TEXT_WEIGHT]);
+        assert!(!index.terms.contains_key("the"));
+        assert!(!index.terms.contains_key("hidden"));
+        assert!(!index.terms.keys().any(|term| term.contains("example")));
+        assert_eq!(index.posts[1].url, "posts/cooking.html");
+
+        let dir = tempfile::tempdir()?;
+        index.write(dir.path())?;
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join(SEARCH_INDEX_FILE))?)?;
+        assert_eq!(written["version"], 1);
+        assert_eq!(written["posts"][0]["title"], "Deploying to Netlify");
+        assert_eq!(
+            written["terms"]["netlifi"],
+            serde_json::json!([0, TITLE_WEIGHT])
+        );
+        Ok(())
+    }
+}
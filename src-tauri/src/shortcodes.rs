@@ -0,0 +1,428 @@
+/// Shortcodes, `{{< name args >}}` on a line of their own in a post's Markdown, expanded into
+/// HTML while building. Writers get embeds without pasting raw HTML, and since the HTML is
+/// generated it's kept even on sites that strip or sanitize the HTML in posts, apart from
+/// shortcodes that load scripts
+use crate::driftwood::is_safe_url;
+use crate::themes::Theme;
+use anyhow::{anyhow, Result};
+use minijinja::{context, Environment};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use regex::Regex;
+use serde::Serialize;
+use std::{collections::BTreeMap, ops::Range, sync::OnceLock};
+
+/// Where a theme keeps its own shortcodes, `shortcodes/NAME.html` under its templates
+pub const SHORTCODE_TEMPLATES: &str = "shortcodes";
+
+/// A built-in shortcode, turns the arguments into HTML
+type Handler = fn(&ShortcodeArgs) -> Result<String>;
+
+/// The shortcodes every site has, themes can replace them with a template of the same name
+static BUILTIN_SHORTCODES: &[(&str, Handler)] =
+    &[("figure", figure), ("gist", gist), ("youtube", youtube)];
+
+/// Built-in shortcodes that put a <script> on the page
+const SCRIPT_SHORTCODES: [&str; 1] = ["gist"];
+
+/// ShortcodeArgs struct
+/// What was written between the name and `>}}`
+/// positional: Arguments in order, like the ID in `{{< youtube dQw4w9WgXcQ >}}`
+/// named: `key=value` arguments, values with spaces go in double quotes
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ShortcodeArgs {
+    pub positional: Vec<String>,
+    pub named: BTreeMap<String, String>,
+}
+
+impl ShortcodeArgs {
+    /// An argument given by name, or failing that by position
+    pub fn get(&self, name: &str, position: usize) -> Option<&str> {
+        self.named
+            .get(name)
+            .or_else(|| self.positional.get(position))
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    fn parse(text: &str) -> Result<ShortcodeArgs> {
+        let mut args = ShortcodeArgs::default();
+        let mut chars = text.trim().chars().peekable();
+        while chars.peek().is_some() {
+            let mut key = None;
+            let mut value = String::new();
+            let mut quoted = false;
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' if !quoted && value.is_empty() => quoted = true,
+                    '"' if quoted => {
+                        quoted = false;
+                        break;
+                    }
+                    '\\' if quoted => value.extend(chars.next()),
+                    '=' if !quoted && key.is_none() => key = Some(std::mem::take(&mut value)),
+                    c if c.is_whitespace() && !quoted => break,
+                    c => value.push(c),
+                }
+            }
+            if quoted {
+                return Err(anyhow!("Unclosed quote"));
+            }
+            match key {
+                Some(key) => {
+                    args.named.insert(key, value);
+                }
+                None => args.positional.push(value),
+            }
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Shortcodes struct
+/// The shortcodes a site's posts can use, the theme's templates first, then the built-in ones
+/// env: The theme's templates, None for only the built-in shortcodes
+/// scripts: Whether the built-in shortcodes that load scripts can be used, off for sites that
+///   don't trust their authors' HTML
+pub struct Shortcodes {
+    env: Option<Environment<'static>>,
+    pub scripts: bool,
+}
+
+impl Shortcodes {
+    /// Only the shortcodes built into the app
+    pub fn builtin() -> Shortcodes {
+        Shortcodes {
+            env: None,
+            scripts: true,
+        }
+    }
+
+    /// The built-in shortcodes plus the theme's `templates/shortcodes/*.html`.
+    /// A template sees the arguments as `args` (a list) and `params` (a map)
+    pub fn for_theme(theme: &Theme) -> Shortcodes {
+        Shortcodes {
+            env: Some(theme.environment()),
+            scripts: true,
+        }
+    }
+
+    /// Expands the shortcodes that stand on a line of their own in a paragraph, leaving the
+    /// ones inside code spans and blocks alone
+    ///
+    /// # Arguments
+    ///
+    /// * `markdown` - The post's Markdown
+    /// * `options` - The options it's parsed with, so code is found the same way
+    /// * `hold` - Takes each shortcode's HTML and returns the text to put in its place
+    ///
+    /// # Returns
+    ///
+    /// The Markdown with shortcodes replaced, and a warning for each one that couldn't be
+    /// expanded, like one in a heading or in the middle of a sentence. Those are left as
+    /// written so the writer can see them
+    pub fn expand(
+        &self,
+        markdown: &str,
+        options: Options,
+        mut hold: impl FnMut(String) -> String,
+    ) -> (String, Vec<String>) {
+        static SHORTCODE: OnceLock<Regex> = OnceLock::new();
+        let shortcode = SHORTCODE.get_or_init(|| {
+            Regex::new(r"\{\{<\s*([A-Za-z][\w-]*)((?:[^>]|>[^}])*?)\s*>\}\}").unwrap()
+        });
+
+        if !markdown.contains("{{<") {
+            return (markdown.to_string(), Vec::new());
+        }
+        let blocks = Blocks::find(markdown, options);
+
+        let mut output = String::with_capacity(markdown.len());
+        let mut warnings = Vec::new();
+        let mut last = 0;
+        for found in shortcode.captures_iter(markdown) {
+            let whole = found.get(0).unwrap();
+            if blocks
+                .code
+                .iter()
+                .any(|range| range.contains(&whole.start()))
+            {
+                continue;
+            }
+            let name = &found[1];
+            if !blocks.stands_alone(markdown, whole.range()) {
+                warnings.push(format!(
+                    "Shortcode {} not expanded: it has to be on a line of its own, outside headings, tables and links",
+                    name
+                ));
+                continue;
+            }
+            match ShortcodeArgs::parse(&found[2]).and_then(|args| self.render(name, &args)) {
+                Ok(html) => {
+                    output.push_str(&markdown[last..whole.start()]);
+                    output.push_str(&hold(html));
+                    last = whole.end();
+                }
+                Err(e) => warnings.push(format!("Shortcode {} not expanded: {:#}", name, e)),
+            }
+        }
+        output.push_str(&markdown[last..]);
+        (output, warnings)
+    }
+
+    fn render(&self, name: &str, args: &ShortcodeArgs) -> Result<String> {
+        if let Some(env) = &self.env {
+            let template = format!("{}/{}.html", SHORTCODE_TEMPLATES, name);
+            match env.get_template(&template) {
+                Ok(template) => {
+                    return Ok(template.render(context! {
+                        name,
+                        args => &args.positional,
+                        params => &args.named,
+                    })?)
+                }
+                Err(e) if e.kind() == minijinja::ErrorKind::TemplateNotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let (_, handler) = BUILTIN_SHORTCODES
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .ok_or_else(|| anyhow!("no shortcode named '{}'", name))?;
+        if !self.scripts && SCRIPT_SHORTCODES.contains(&name) {
+            return Err(anyhow!(
+                "it loads a script, which this site's HTML setting doesn't allow"
+            ));
+        }
+        handler(args)
+    }
+}
+
+/// Blocks struct
+/// Byte ranges in the Markdown that decide where a shortcode can be expanded
+/// paragraphs: Where block HTML can replace a line
+/// code: Code spans and blocks, shortcodes in them are examples and left alone
+/// inline: Links and images, where HTML would end up in an attribute or inside an <a>
+struct Blocks {
+    paragraphs: Vec<Range<usize>>,
+    code: Vec<Range<usize>>,
+    inline: Vec<Range<usize>>,
+}
+
+impl Blocks {
+    fn find(markdown: &str, options: Options) -> Blocks {
+        let mut blocks = Blocks {
+            paragraphs: Vec::new(),
+            code: Vec::new(),
+            inline: Vec::new(),
+        };
+        for (event, range) in Parser::new_ext(markdown, options).into_offset_iter() {
+            match event {
+                Event::Start(Tag::Paragraph) => blocks.paragraphs.push(range),
+                Event::Code(_) | Event::Start(Tag::CodeBlock(_)) => blocks.code.push(range),
+                Event::Start(Tag::Link { .. } | Tag::Image { .. }) => blocks.inline.push(range),
+                _ => {}
+            }
+        }
+        blocks
+    }
+
+    /// Whether a shortcode is the only thing on its line of a paragraph
+    fn stands_alone(&self, markdown: &str, shortcode: Range<usize>) -> bool {
+        let Some(paragraph) = self
+            .paragraphs
+            .iter()
+            .find(|paragraph| paragraph.start <= shortcode.start && shortcode.end <= paragraph.end)
+        else {
+            return false;
+        };
+        if self
+            .inline
+            .iter()
+            .any(|range| range.contains(&shortcode.start))
+        {
+            return false;
+        }
+
+        let line_start = markdown[..shortcode.start]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1)
+            .max(paragraph.start);
+        let line_end = markdown[shortcode.end..]
+            .find('\n')
+            .map_or(markdown.len(), |newline| shortcode.end + newline)
+            .min(paragraph.end);
+        markdown[line_start..shortcode.start].trim().is_empty()
+            && markdown[shortcode.end..line_end].trim().is_empty()
+    }
+}
+
+/// `{{< youtube id [title] >}}`, embedded from youtube-nocookie.com.
+/// `start=90` starts playing 90 seconds in
+fn youtube(args: &ShortcodeArgs) -> Result<String> {
+    let id = args
+        .get("id", 0)
+        .ok_or_else(|| anyhow!("the video ID is missing"))?;
+    if !is_token(id) {
+        return Err(anyhow!("'{}' is not a YouTube video ID", id));
+    }
+    let start = match args.get("start", 2) {
+        Some(start) => {
+            let seconds: u32 = start
+                .parse()
+                .map_err(|_| anyhow!("start should be a number of seconds"))?;
+            format!("?start={}", seconds)
+        }
+        None => String::new(),
+    };
+    Ok(format!(
+        "<div class=\"shortcode-youtube\"><iframe src=\"https://www.youtube-nocookie.com/embed/{}{}\" title=\"{}\" loading=\"lazy\" allow=\"accelerometer; clipboard-write; encrypted-media; gyroscope; picture-in-picture; web-share\" referrerpolicy=\"strict-origin-when-cross-origin\" allowfullscreen></iframe></div>",
+        id,
+        start,
+        escape(args.get("title", 1).unwrap_or("YouTube video"))
+    ))
+}
+
+/// `{{< figure src [caption] >}}`, an image with a caption. `alt=` defaults to the caption
+fn figure(args: &ShortcodeArgs) -> Result<String> {
+    let src = args
+        .get("src", 0)
+        .ok_or_else(|| anyhow!("the image's src is missing"))?;
+    if !is_safe_url(src) {
+        return Err(anyhow!("'{}' is not a safe image URL", src));
+    }
+    let caption = args.get("caption", 1);
+    let alt = args.get("alt", 2).or(caption).unwrap_or_default();
+
+    let mut html = format!(
+        "<figure class=\"shortcode-figure\"><img src=\"{}\" alt=\"{}\" loading=\"lazy\">",
+        escape(src),
+        escape(alt)
+    );
+    if let Some(caption) = caption {
+        html.push_str(&format!("<figcaption>{}</figcaption>", escape(caption)));
+    }
+    html.push_str("</figure>");
+    Ok(html)
+}
+
+/// `{{< gist user id [file] >}}`, a GitHub gist
+fn gist(args: &ShortcodeArgs) -> Result<String> {
+    let user = args
+        .get("user", 0)
+        .ok_or_else(|| anyhow!("the gist's user is missing"))?;
+    let id = args
+        .get("id", 1)
+        .ok_or_else(|| anyhow!("the gist's ID is missing"))?;
+    if !is_token(user) || !is_token(id) {
+        return Err(anyhow!("'{}/{}' is not a gist", user, id));
+    }
+    let file = match args.get("file", 2) {
+        Some(file) if is_token(&file.replace('.', "")) => format!("?file={}", file),
+        Some(file) => return Err(anyhow!("'{}' is not a file name", file)),
+        None => String::new(),
+    };
+    Ok(format!(
+        "<script src=\"https://gist.github.com/{}/{}.js{}\"></script>",
+        user, id, file
+    ))
+}
+
+/// IDs and user names go into URLs as is, so they're held to letters, digits, - and _
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_expand_shortcodes() -> Result<()> {
+        let markdown = r#"Watch this:
+
+{{< youtube dQw4w9WgXcQ title="Never <gonna>" >}}
+
+{{< figure src="/assets/cat.png" caption="A \"cat\"" >}}
+`{{< youtube kept >}}` and {{< youtube inline >}}
+
+```
+{{< gist also kept >}}
+```
+
+{{< youtube "bad id" >}}
+{{< nope >}}
+{{< note >}}
+
+# Heading {{< youtube heading >}}
+
+![{{< youtube alt >}}](/assets/cat.png)
+"#;
+        let mut held = Vec::new();
+        let (output, warnings) = Shortcodes::builtin().expand(markdown, Options::empty(), |html| {
+            held.push(html);
+            format!("[{}]", held.len() - 1)
+        });
+
+        assert!(output.starts_with(
+            "Watch this:\n\n[0]\n\n[1]\n`{{< youtube kept >}}` and {{< youtube inline >}}"
+        ));
+        assert!(output.contains("{{< gist also kept >}}"));
+        assert!(output.contains("{{< youtube \"bad id\" >}}\n{{< nope >}}"));
+        // block HTML can't go in the middle of a sentence, a heading or an image's alt text
+        assert!(output.contains("# Heading {{< youtube heading >}}"));
+        assert!(output.contains("![{{< youtube alt >}}]"));
+        assert!(held[0].contains(r#"src="https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ""#));
+        assert!(held[0].contains(r#"title="Never &lt;gonna&gt;""#));
+        assert_eq!(
+            held[1],
+            r#"<figure class="shortcode-figure"><img src="/assets/cat.png" alt="A &quot;cat&quot;" loading="lazy"><figcaption>A &quot;cat&quot;</figcaption></figure>"#
+        );
+        assert_eq!(warnings.len(), 6);
+        assert!(warnings[0].contains("youtube not expanded: it has to be on a line of its own"));
+        assert!(warnings[2].contains("no shortcode named 'nope'"));
+        assert!(warnings[4].contains("line of its own") && warnings[5].contains("line of its own"));
+
+        // themes add shortcodes and replace built-in ones with templates
+        let dir = tempfile::tempdir()?;
+        let templates = dir.path().join("templates").join(SHORTCODE_TEMPLATES);
+        fs::create_dir_all(&templates)?;
+        fs::write(
+            templates.join("note.html"),
+            r#"<aside class="{{ params.kind }}">{{ args[0] }}</aside>"#,
+        )?;
+        let mut theme = Theme::builtin();
+        theme.override_dir = Some(dir.path().to_path_buf());
+        let mut shortcodes = Shortcodes::for_theme(&theme);
+        let markdown =
+            "{{< note \"<b>Hi</b>\" kind=tip >}}\n{{< gist octocat 6cad326836d38bd3a7ae >}}";
+        let (output, warnings) = shortcodes.expand(markdown, Options::empty(), |html| html);
+        assert!(warnings.is_empty());
+        assert_eq!(
+            output,
+            "<aside class=\"tip\">&lt;b&gt;Hi&lt;&#x2f;b&gt;</aside>\n<script src=\"https://gist.github.com/octocat/6cad326836d38bd3a7ae.js\"></script>"
+        );
+
+        // sites that don't trust their authors' HTML don't get scripts from shortcodes either
+        shortcodes.scripts = false;
+        let (output, warnings) = shortcodes.expand(markdown, Options::empty(), |html| html);
+        assert!(output.ends_with("\n{{< gist octocat 6cad326836d38bd3a7ae >}}"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("gist not expanded: it loads a script"));
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 /// CRUD operations for the sites table
-use crate::{db::DB_PATH, driftwood::SiteDetails};
+use crate::{
+    db::DB_PATH,
+    driftwood::{SiteDetails, SiteSettings},
+};
 use rusqlite::{params, Connection, OptionalExtension, Result};
 use std::path::Path;
 
@@ -109,6 +112,48 @@ impl SiteRepository {
         Ok(sites)
     }
 
+    /// Reads a site's settings, sites that never saved any get the defaults on a single page,
+    /// the way they were built before pagination
+    pub fn read_settings(&self, site_id: &str) -> Result<SiteSettings> {
+        let settings: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT settings FROM sites WHERE id = ?1",
+                params![site_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        let to_sql_error = |e: serde_json::Error| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+        };
+        match settings.flatten() {
+            Some(json) => {
+                let mut value: serde_json::Value =
+                    serde_json::from_str(&json).map_err(to_sql_error)?;
+                // settings saved before pagination keep all their posts on one page
+                if let Some(fields) = value.as_object_mut() {
+                    fields.entry("posts_per_page").or_insert(0.into());
+                }
+                serde_json::from_value(value).map_err(to_sql_error)
+            }
+            None => Ok(SiteSettings {
+                posts_per_page: 0,
+                ..SiteSettings::default()
+            }),
+        }
+    }
+
+    pub fn update_settings(&self, site_id: &str, settings: &SiteSettings) -> Result<()> {
+        let json = serde_json::to_string(settings)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.execute(
+            "UPDATE sites SET settings = ?1 WHERE id = ?2",
+            params![json, site_id],
+        )?;
+        Ok(())
+    }
+
     pub fn refresh_sites(mut self, netlify_sites: Vec<SiteDetails>) -> Result<()> {
         // Start a transaction for atomicity
         let tx = self.conn.transaction()?;
@@ -184,4 +229,33 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_settings_from_before_pagination_keep_one_page() -> Result<()> {
+        let repo = SiteRepository {
+            conn: Connection::open_in_memory()?,
+        };
+        repo.conn.execute_batch(
+            "CREATE TABLE sites (id TEXT PRIMARY KEY, settings TEXT);
+             INSERT INTO sites (id, settings) VALUES ('old', '{\"theme\":\"default\"}'), ('unset', NULL);",
+        )?;
+        assert_eq!(repo.read_settings("old")?.posts_per_page, 0);
+        assert_eq!(repo.read_settings("unset")?.posts_per_page, 0);
+        // only stored rows are treated as old, missing fields otherwise take the defaults
+        let settings: SiteSettings = serde_json::from_str("{}").unwrap();
+        assert_eq!(
+            settings.posts_per_page,
+            crate::builder::DEFAULT_POSTS_PER_PAGE
+        );
+
+        // new sites save the defaults, which paginate
+        repo.conn
+            .execute("INSERT INTO sites (id) VALUES ('new')", [])?;
+        repo.update_settings("new", &SiteSettings::default())?;
+        assert_eq!(
+            repo.read_settings("new")?.posts_per_page,
+            crate::builder::DEFAULT_POSTS_PER_PAGE
+        );
+        Ok(())
+    }
 }
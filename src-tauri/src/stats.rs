@@ -0,0 +1,123 @@
+/// Content statistics, counted from a post's Markdown whenever it's saved
+use crate::driftwood::MarkdownOptions;
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+
+/// Reading speed the reading time is estimated with
+pub const WORDS_PER_MINUTE: u32 = 200;
+
+/// PostStats struct
+/// What editors check a post's length against, stored with the post
+/// word_count: Words of prose, code blocks aren't counted
+/// reading_time: Minutes at WORDS_PER_MINUTE, rounded up
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct PostStats {
+    pub word_count: u32,
+    pub reading_time: u32,
+    pub heading_count: u32,
+    pub image_count: u32,
+    pub link_count: u32,
+}
+
+impl PostStats {
+    /// Counts the statistics for a post
+    ///
+    /// # Arguments
+    ///
+    /// * `markdown` - The post's content
+    /// * `options` - The Markdown extensions it's written with
+    pub fn from_markdown(markdown: &str, options: &MarkdownOptions) -> PostStats {
+        let mut stats = PostStats::default();
+        let mut in_code_block = false;
+        // words can be split over several events, e.g. "**bold**ly"
+        let mut in_word = false;
+
+        for event in Parser::new_ext(markdown, options.parser_options()) {
+            match event {
+                Event::Start(Tag::Heading { .. }) => stats.heading_count += 1,
+                Event::Start(Tag::Image { .. }) => stats.image_count += 1,
+                Event::Start(Tag::Link { .. }) => stats.link_count += 1,
+                Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                Event::End(TagEnd::CodeBlock) => in_code_block = false,
+                Event::Text(text) | Event::Code(text) if !in_code_block => {
+                    stats.word_count += count_words(&text, &mut in_word);
+                }
+                // inline formatting doesn't end a word, anything else does
+                Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough)
+                | Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough) => {}
+                Event::Start(_) | Event::End(_) | Event::SoftBreak | Event::HardBreak => {
+                    in_word = false
+                }
+                _ => {}
+            }
+        }
+
+        stats.reading_time = stats.word_count.div_ceil(WORDS_PER_MINUTE);
+        stats
+    }
+}
+
+/// SiteStats struct
+/// Every post on a site added up, for the dashboard
+/// average_word_count: Rounded down, 0 for a site without posts
+/// longest_word_count: Words in the site's longest post
+/// reading_time: Minutes to read every post
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Default)]
+pub struct SiteStats {
+    pub post_count: u32,
+    pub word_count: u32,
+    pub average_word_count: u32,
+    pub longest_word_count: u32,
+    pub reading_time: u32,
+    pub heading_count: u32,
+    pub image_count: u32,
+    pub link_count: u32,
+}
+
+/// Counts the words in a piece of text, a word being a run of characters with a letter or digit in it
+fn count_words(text: &str, in_word: &mut bool) -> u32 {
+    let mut count = 0;
+    let mut word_has_content = *in_word;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            *in_word = false;
+            word_has_content = false;
+        } else if c.is_alphanumeric() && !word_has_content {
+            count += 1;
+            *in_word = true;
+            word_has_content = true;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_markdown() {
+        let markdown = "# Title\n\nSome **bold**ly written words - and [a link](https://example.com).\n\n![An image](cat.png)\n\n```rust\nlet code = \"not counted\";\n```\n\n## Second\n\nUse `cargo build`, <https://example.org>.\n";
+        let stats = PostStats::from_markdown(markdown, &MarkdownOptions::default());
+        assert_eq!(
+            stats,
+            PostStats {
+                // Title, Some boldly written words and a link, An image, Second, Use cargo build https...
+                word_count: 15,
+                reading_time: 1,
+                heading_count: 2,
+                image_count: 1,
+                link_count: 2,
+            }
+        );
+
+        let long = "word ".repeat(401);
+        let stats = PostStats::from_markdown(&long, &MarkdownOptions::default());
+        assert_eq!((stats.word_count, stats.reading_time), (401, 3));
+        assert_eq!(
+            PostStats::from_markdown("", &MarkdownOptions::default()),
+            PostStats::default()
+        );
+    }
+}
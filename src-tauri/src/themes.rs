@@ -0,0 +1,345 @@
+/// Themes, a directory of templates, static files and a manifest that decides how a site looks
+use crate::builder::copy_dir;
+use crate::driftwood::{is_safe_url, parse_post_date, slugify, SiteDetails};
+use crate::images::{srcset, ImageSource};
+use anyhow::{anyhow, Context, Result};
+use minijinja::{value::ViaDeserialize, AutoEscape, Environment};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Where installed themes live, one directory per theme
+pub const THEMES_DIR: &str = "themes";
+
+/// The theme compiled into the binary, used when a site hasn't picked one
+pub const DEFAULT_THEME: &str = "default";
+
+/// Template rendered once per site for the front page
+pub const INDEX_TEMPLATE: &str = "index.html";
+/// Template rendered for each post's page
+pub const POST_TEMPLATE: &str = "post.html";
+/// Template rendered for each tag's archive, the default one is the index with a heading
+pub const TAG_TEMPLATE: &str = "tag.html";
+
+/// Format the date filter uses when a template doesn't give one, e.g. November 2, 2024
+const DEFAULT_DATE_FORMAT: &str = "%B %-d, %Y";
+
+/// The built-in default theme, as (template name, contents).
+/// Pages extend base.html, the index includes partials/card.html for each post
+/// and tag archives extend the index
+static BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("base.html", include_str!("themes/default/templates/base.html")),
+    (INDEX_TEMPLATE, include_str!("themes/default/templates/index.html")),
+    (POST_TEMPLATE, include_str!("themes/default/templates/post.html")),
+    (TAG_TEMPLATE, include_str!("themes/default/templates/tag.html")),
+    ("partials/card.html", include_str!("themes/default/templates/partials/card.html")),
+];
+static BUILTIN_MANIFEST: &str = include_str!("themes/default/theme.json");
+
+/// ThemeManifest struct
+/// The theme.json at the root of a theme directory
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ThemeManifest {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub author: String,
+}
+
+/// Theme struct
+/// A theme resolved for one site
+/// dir: The theme's directory, None for the built-in default
+/// override_dir: The site's `theme` directory, its templates and static files win over the theme's
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub manifest: ThemeManifest,
+    pub dir: Option<PathBuf>,
+    pub override_dir: Option<PathBuf>,
+}
+
+impl Theme {
+    /// Loads an installed theme by name, "default" is the built-in one unless it's been installed over
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The theme's directory name under `themes/`
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the Theme, without a site override
+    pub fn load(name: &str) -> Result<Theme> {
+        Theme::load_from(Path::new(THEMES_DIR), name)
+    }
+
+    fn load_from(themes_dir: &Path, name: &str) -> Result<Theme> {
+        if !is_valid_theme_name(name) {
+            return Err(anyhow!("'{}' is not a valid theme name", name));
+        }
+
+        let dir = themes_dir.join(name);
+        if !dir.is_dir() {
+            if name == DEFAULT_THEME {
+                return Ok(Theme::builtin());
+            }
+            return Err(anyhow!("Theme '{}' is not installed", name));
+        }
+
+        let manifest_path = dir.join("theme.json");
+        let mut manifest: ThemeManifest = match fs::read_to_string(&manifest_path) {
+            Ok(manifest) => serde_json::from_str(&manifest)
+                .with_context(|| format!("Invalid manifest for theme '{}'", name))?,
+            Err(_) => ThemeManifest::default(),
+        };
+        if manifest.name.is_empty() {
+            manifest.name = name.to_string();
+        }
+
+        Ok(Theme {
+            manifest,
+            dir: Some(dir),
+            override_dir: None,
+        })
+    }
+
+    /// The default theme that ships inside the app
+    pub fn builtin() -> Theme {
+        Theme {
+            manifest: serde_json::from_str(BUILTIN_MANIFEST).unwrap_or_default(),
+            dir: None,
+            override_dir: None,
+        }
+    }
+
+    /// The theme a site picked in its settings, with the site's override directory applied
+    ///
+    /// # Arguments
+    ///
+    /// * `site` - The site being built
+    /// * `name` - The theme name from the site's settings
+    pub fn for_site(site: &SiteDetails, name: &str) -> Result<Theme> {
+        let mut theme = Theme::load(name)?;
+        theme.override_dir = Some(site.build_site_path()?.join("theme"));
+        Ok(theme)
+    }
+
+    /// Reads a template, checking the site override, then the theme, then the built-in default.
+    /// Themes only need to ship the templates they change
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The template's path under `templates/`, e.g. post.html or partials/card.html
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the template source
+    pub fn template(&self, name: &str) -> Result<String> {
+        self.find_template(name)?
+            .ok_or_else(|| anyhow!("Theme '{}' has no template {}", self.manifest.name, name))
+    }
+
+    fn find_template(&self, name: &str) -> Result<Option<String>> {
+        // templates can include each other, keep them inside the templates directory
+        if name.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            return Err(anyhow!("'{}' is not a valid template name", name));
+        }
+
+        for dir in [&self.override_dir, &self.dir].into_iter().flatten() {
+            let path = dir.join("templates").join(name);
+            if path.is_file() {
+                return fs::read_to_string(&path)
+                    .map(Some)
+                    .with_context(|| format!("Failed to read template {}", path.display()));
+            }
+        }
+
+        Ok(BUILTIN_TEMPLATES
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, source)| source.to_string()))
+    }
+
+    /// A template environment that loads this theme's templates, with the filters themes can use:
+    /// `date` reformats a post date, e.g. `post.date | date("%Y")`, `slug` makes text URL safe
+    /// and `url` blanks out javascript: and other unsafe URLs before they go in an href or src.
+    /// Everything is HTML escaped unless a template marks it `| safe`, whatever the template's extension
+    pub fn environment(&self) -> Environment<'static> {
+        let mut env = Environment::new();
+        env.set_auto_escape_callback(|name| match name.rsplit_once('.') {
+            Some((_, "txt")) => AutoEscape::None,
+            _ => AutoEscape::Html,
+        });
+        let theme = self.clone();
+        env.set_loader(move |name| {
+            theme.find_template(name).map_err(|e| {
+                minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, e.to_string())
+            })
+        });
+        env.add_filter("date", date_filter);
+        env.add_filter("slug", slug_filter);
+        env.add_filter("url", url_filter);
+        env.add_filter("rel", rel_filter);
+        env.add_filter("srcset", srcset_filter);
+        env
+    }
+
+    /// Copies the theme's static files into the build, then the site's so they can replace them
+    pub fn copy_static(&self, out_dir: &Path) -> Result<()> {
+        for dir in [&self.dir, &self.override_dir].into_iter().flatten() {
+            let static_dir = dir.join("static");
+            if static_dir.is_dir() {
+                copy_dir(&static_dir, out_dir)
+                    .with_context(|| format!("Failed to copy {}", static_dir.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Directories whose changes should trigger a rebuild
+    pub fn watch_paths(&self) -> Vec<PathBuf> {
+        [&self.dir, &self.override_dir]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Lists the themes a site can pick, the built-in default first
+pub fn list_themes() -> Result<Vec<ThemeManifest>> {
+    list_themes_in(Path::new(THEMES_DIR))
+}
+
+fn list_themes_in(themes_dir: &Path) -> Result<Vec<ThemeManifest>> {
+    let mut themes = vec![Theme::load_from(themes_dir, DEFAULT_THEME)?.manifest];
+    // keep the directory name, it's what sites store in their settings
+    themes[0].name = DEFAULT_THEME.to_string();
+
+    if themes_dir.is_dir() {
+        let mut names: Vec<String> = fs::read_dir(themes_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name != DEFAULT_THEME && is_valid_theme_name(name))
+            .collect();
+        names.sort();
+
+        for name in names {
+            match Theme::load_from(themes_dir, &name) {
+                Ok(theme) => themes.push(ThemeManifest {
+                    name,
+                    ..theme.manifest
+                }),
+                Err(e) => println!("> Skipping theme {}: {}", name, e),
+            }
+        }
+    }
+
+    Ok(themes)
+}
+
+/// Reformats a post date with a chrono format string, dates it can't read are left as they are
+fn date_filter(value: String, format: Option<String>) -> String {
+    match parse_post_date(&value) {
+        Some(date) => date
+            .format(format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT))
+            .to_string(),
+        None => value,
+    }
+}
+
+fn slug_filter(value: String) -> String {
+    slugify(&value)
+}
+
+fn url_filter(value: String) -> String {
+    if is_safe_url(&value) {
+        value
+    } else {
+        String::new()
+    }
+}
+
+/// Makes a site-absolute path like `/assets/x.png` relative to the page through its root,
+/// so it works wherever the build is served from. Full URLs are left alone
+fn rel_filter(value: String, root: String) -> String {
+    if value.is_empty() || value.starts_with("//") || value.contains(':') {
+        return value;
+    }
+    format!("{}{}", root, value.trim_start_matches('/'))
+}
+
+/// Turns `post.image_set.sources` or `.webp` into a srcset value relative to the page
+fn srcset_filter(sources: ViaDeserialize<Vec<ImageSource>>, root: String) -> String {
+    srcset(&sources, &root)
+}
+
+/// Theme names are directory names, don't let them point anywhere else
+fn is_valid_theme_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_resolution() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let themes_dir = dir.path().join("themes");
+        let theme_dir = themes_dir.join("paper");
+        fs::create_dir_all(theme_dir.join("templates"))?;
+        fs::create_dir_all(theme_dir.join("static"))?;
+        fs::write(theme_dir.join("theme.json"), r#"{"name": "Paper", "version": "0.1.0"}"#)?;
+        fs::write(theme_dir.join("templates").join(POST_TEMPLATE), "paper post")?;
+        fs::write(theme_dir.join("templates").join(INDEX_TEMPLATE), "paper index")?;
+        fs::write(theme_dir.join("static").join("style.css"), "paper css")?;
+
+        let mut theme = Theme::load_from(&themes_dir, "paper")?;
+        assert_eq!(theme.manifest.name, "Paper");
+        assert_eq!(theme.template(POST_TEMPLATE)?, "paper post");
+        // templates the theme doesn't ship come from the default
+        assert!(theme.template("partials/card.html")?.contains("card__title"));
+        assert!(theme.template("../theme.json").is_err());
+
+        let override_dir = dir.path().join("site-theme");
+        fs::create_dir_all(override_dir.join("templates"))?;
+        fs::create_dir_all(override_dir.join("static"))?;
+        fs::write(override_dir.join("templates").join(INDEX_TEMPLATE), "site index")?;
+        fs::write(override_dir.join("static").join("style.css"), "site css")?;
+        theme.override_dir = Some(override_dir);
+        assert_eq!(theme.template(INDEX_TEMPLATE)?, "site index");
+        assert_eq!(theme.template(POST_TEMPLATE)?, "paper post");
+
+        let out_dir = dir.path().join("build");
+        theme.copy_static(&out_dir)?;
+        assert_eq!(fs::read_to_string(out_dir.join("style.css"))?, "site css");
+
+        assert!(Theme::load_from(&themes_dir, "missing").is_err());
+        assert!(Theme::load_from(&themes_dir, "../paper").is_err());
+        assert!(Theme::load_from(&themes_dir, DEFAULT_THEME)?.dir.is_none());
+
+        let env = theme.environment();
+        let rendered = env.render_str(
+            r#"{{ date | date("%Y-%m-%d") }} {{ date | date }} {{ tag | slug }} [{{ link | url }}]"#,
+            minijinja::context! {
+                date => "2024/11/02 09:30 PM",
+                tag => "Rust & Tauri",
+                link => " JavaScript:alert(1)",
+            },
+        )?;
+        assert_eq!(rendered, "2024-11-02 November 2, 2024 rust-tauri []");
+
+        let names: Vec<String> = list_themes_in(&themes_dir)?
+            .into_iter()
+            .map(|theme| theme.name)
+            .collect();
+        assert_eq!(names, vec!["default", "paper"]);
+        Ok(())
+    }
+}
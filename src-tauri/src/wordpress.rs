@@ -0,0 +1,566 @@
+/// Imports posts from a WordPress export (WXR) file into a Driftwood site
+use crate::assets::{AssetStore, IMAGE_EXTENSIONS};
+use crate::driftwood::{MarkdownOptions, Post, SiteDetails, POST_DATE_FORMAT};
+use crate::posts::PostRepository;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDateTime};
+use quick_xml::{events::Event, Reader};
+use regex::Regex;
+use serde::Serialize;
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+
+/// Category WordPress puts posts in when none was picked, not worth keeping as a tag
+const DEFAULT_CATEGORY: &str = "Uncategorized";
+
+/// WxrItem struct
+/// A single `<item>` from the export, posts, pages and attachments all use it
+#[derive(Debug, Default, Clone)]
+pub struct WxrItem {
+    pub title: String,
+    pub post_name: String,
+    pub post_id: String,
+    pub post_type: String,
+    pub status: String,
+    pub post_date: String,
+    pub pub_date: String,
+    pub content: String,
+    pub excerpt: String,
+    pub categories: Vec<String>,
+    pub tags: Vec<String>,
+    pub attachment_url: String,
+    pub thumbnail_id: String,
+}
+
+/// FlaggedImage struct
+/// An image that couldn't be downloaded and needs to be handled by hand
+#[derive(Serialize, Debug)]
+pub struct FlaggedImage {
+    pub post_title: String,
+    pub url: String,
+    pub reason: String,
+}
+
+/// ImportReport struct
+/// Summary of an import, sent back to the client
+#[derive(Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: Vec<String>,
+    pub images_downloaded: usize,
+    pub flagged_images: Vec<FlaggedImage>,
+}
+
+/// Parses the contents of a WXR file into its items
+///
+/// # Arguments
+///
+/// * `xml` - The contents of the export file
+///
+/// # Returns
+///
+/// A Result containing every item in the export, in file order
+pub fn parse_wxr(xml: &str) -> Result<Vec<WxrItem>> {
+    let mut reader = Reader::from_str(xml);
+    let mut items = Vec::new();
+    let mut current: Option<WxrItem> = None;
+    let mut text = String::new();
+    let mut category_domain = String::new();
+    let mut meta_key = String::new();
+
+    loop {
+        match reader
+            .read_event()
+            .context("Failed to parse the WordPress export")?
+        {
+            Event::Start(e) => {
+                text.clear();
+                if e.name().as_ref() == b"item" {
+                    current = Some(WxrItem::default());
+                } else if e.name().as_ref() == b"category" {
+                    category_domain = match e.try_get_attribute("domain")? {
+                        Some(attr) => attr.unescape_value()?.to_string(),
+                        None => String::new(),
+                    };
+                }
+            }
+            Event::Text(e) => text.push_str(&e.unescape()?),
+            Event::CData(e) => text.push_str(&String::from_utf8_lossy(&e.into_inner())),
+            Event::End(e) => {
+                let name = e.name();
+                if name.as_ref() == b"item" {
+                    if let Some(item) = current.take() {
+                        items.push(item);
+                    }
+                } else if let Some(item) = current.as_mut() {
+                    let value = text.trim().to_string();
+                    match name.as_ref() {
+                        b"title" => item.title = value,
+                        b"pubDate" => item.pub_date = value,
+                        b"content:encoded" => item.content = value,
+                        b"excerpt:encoded" => item.excerpt = value,
+                        b"wp:post_id" => item.post_id = value,
+                        b"wp:post_name" => item.post_name = value,
+                        b"wp:post_type" => item.post_type = value,
+                        b"wp:status" => item.status = value,
+                        b"wp:post_date" => item.post_date = value,
+                        b"wp:attachment_url" => item.attachment_url = value,
+                        b"wp:meta_key" => meta_key = value,
+                        b"wp:meta_value" if meta_key == "_thumbnail_id" => {
+                            item.thumbnail_id = value
+                        }
+                        b"category" => match category_domain.as_str() {
+                            "category" => item.categories.push(value),
+                            "post_tag" => item.tags.push(value),
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(items)
+}
+
+/// Imports every published post in a WXR file into a site
+///
+/// # Arguments
+///
+/// * `path` - Path to the export file
+/// * `site` - The site the posts are created in
+/// * `post_repo` - Repository the posts are saved through
+/// * `options` - The site's Markdown extensions, the posts' statistics are counted with them
+/// * `download_images` - Whether to fetch images into the site's asset store,
+///   when false every image is flagged instead
+///
+/// # Returns
+///
+/// A Result containing the ImportReport
+pub fn import_wxr(
+    path: &Path,
+    site: &SiteDetails,
+    post_repo: &PostRepository,
+    options: &MarkdownOptions,
+    download_images: bool,
+) -> Result<ImportReport> {
+    println!("> Importing WordPress export: {}", path.display());
+
+    let site_id = site
+        .id
+        .clone()
+        .ok_or_else(|| anyhow!("Site has no ID, can't import into it"))?;
+    let xml = fs::read_to_string(path).context("Failed to read the WordPress export")?;
+    let items = parse_wxr(&xml)?;
+
+    // featured images point at attachment items by ID
+    let attachments: HashMap<String, String> = items
+        .iter()
+        .filter(|item| item.post_type == "attachment" && !item.attachment_url.is_empty())
+        .map(|item| (item.post_id.clone(), item.attachment_url.clone()))
+        .collect();
+
+    let mut images = ImageImporter::new(site, download_images)?;
+    let mut report = ImportReport::default();
+
+    for item in items.iter().filter(|item| item.post_type == "post") {
+        // Driftwood deploys every post it has, so only bring over what was live
+        if item.status != "publish" {
+            report
+                .skipped
+                .push(format!("{} ({})", item.title, item.status));
+            continue;
+        }
+
+        // keep the WordPress slug so links to the old site still line up
+        let slug = urlencoding::decode(&item.post_name)
+            .map(|slug| slug.to_string())
+            .unwrap_or_default();
+        // importing the same export twice shouldn't double every post, a post that only shares
+        // its slug with one written in Driftwood is imported under a unique slug instead
+        if post_repo
+            .is_imported(&site_id, &item.post_id)
+            .context("Failed to check for already imported posts")?
+        {
+            report
+                .skipped
+                .push(format!("{} (already imported)", item.title));
+            continue;
+        }
+
+        println!("> Importing post: {}", item.title);
+
+        let mut post = Post::new(item.title.clone());
+        post.slug = slug;
+        post.date = convert_date(&item.post_date, &item.pub_date);
+        post.excerpt = item.excerpt.clone();
+        post.tags = merge_tags(&item.categories, &item.tags);
+
+        let html = images.localize(&wpautop(&item.content), &item.title, &mut report);
+        post.content = html_to_markdown(&html);
+
+        if let Some(url) = attachments.get(&item.thumbnail_id) {
+            post.image = images.import(url, &item.title, &mut report);
+        }
+
+        let slug = post_repo
+            .create(&post, &site_id, options)
+            .with_context(|| format!("Failed to save imported post '{}'", item.title))?;
+        post_repo
+            .set_wordpress_id(&site_id, &slug, &item.post_id)
+            .with_context(|| format!("Failed to save imported post '{}'", item.title))?;
+        report.imported += 1;
+    }
+
+    println!(
+        "> Imported {} posts, skipped {}, flagged {} images",
+        report.imported,
+        report.skipped.len(),
+        report.flagged_images.len()
+    );
+
+    Ok(report)
+}
+
+/// Converts WordPress post HTML to Markdown
+pub fn html_to_markdown(html: &str) -> String {
+    // block editor comments like <!-- wp:paragraph --> carry nothing we can use
+    let block_comments = Regex::new(r"<!--\s*/?wp:[^>]*-->").unwrap();
+    let html = block_comments.replace_all(html, "");
+    html2md::parse_html(&html).trim().to_string()
+}
+
+/// The classic editor stores paragraphs as blank lines instead of <p> tags,
+/// this wraps them the same way WordPress does when it renders the post
+pub fn wpautop(content: &str) -> String {
+    // <pre>, <picture> and the like don't mean the paragraphs are already there
+    let paragraph = Regex::new(r"<p[\s>]").unwrap();
+    if paragraph.is_match(content) || content.contains("<!-- wp:") {
+        return content.to_string();
+    }
+
+    let content = content
+        .replace("\r\n", "\n")
+        .replace(['\u{E000}', '\u{E001}'], "");
+    let (content, held) = hold_blocks(&content);
+    let headings = Regex::new(r"^<h[1-6][\s>]").unwrap();
+    content
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| {
+            let held_block = chunk
+                .strip_prefix('\u{E000}')
+                .and_then(|rest| rest.strip_suffix('\u{E001}'))
+                .and_then(|index| index.parse::<usize>().ok())
+                .and_then(|index| held.get(index));
+            match held_block {
+                Some(block) => block.clone(),
+                None if headings.is_match(chunk) => chunk.to_string(),
+                None => format!("<p>{}</p>", chunk.replace('\n', "<br />\n")),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Takes the block elements out of classic editor content before it's split on blank lines,
+/// so a <pre> or <blockquote> with a blank line in it stays whole
+///
+/// # Returns
+///
+/// The content with each block swapped for a placeholder on its own line, and the blocks
+fn hold_blocks(content: &str) -> (String, Vec<String>) {
+    let open = Regex::new(r"(?i)<(pre|blockquote|table|ul|ol|div|figure)[\s>]").unwrap();
+    let mut text = String::with_capacity(content.len());
+    let mut held = Vec::new();
+    let mut rest = content;
+    while let Some(found) = open.captures(rest) {
+        let start = found.get(0).unwrap().start();
+        let end = start + block_len(&rest[start..], &found[1]);
+        text.push_str(&rest[..start]);
+        text.push_str(&format!("\n\n\u{E000}{}\u{E001}\n\n", held.len()));
+        held.push(rest[start..end].to_string());
+        rest = &rest[end..];
+    }
+    text.push_str(rest);
+    (text, held)
+}
+
+/// Length of the element html starts with, up to its matching close tag or the end when it has none
+fn block_len(html: &str, tag: &str) -> usize {
+    let tags = Regex::new(&format!(r"(?i)<(/?){}\b[^>]*>", tag)).unwrap();
+    let mut depth = 0;
+    for found in tags.captures_iter(html) {
+        if found[1].is_empty() {
+            depth += 1;
+        } else {
+            depth -= 1;
+            if depth == 0 {
+                return found.get(0).unwrap().end();
+            }
+        }
+    }
+    html.len()
+}
+
+/// Converts WordPress' post date into Driftwood's format, falling back to the
+/// RSS pubDate and then the current time
+pub fn convert_date(post_date: &str, pub_date: &str) -> String {
+    if let Ok(date) = NaiveDateTime::parse_from_str(post_date, "%Y-%m-%d %H:%M:%S") {
+        return date.format(POST_DATE_FORMAT).to_string();
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(pub_date) {
+        return date.format(POST_DATE_FORMAT).to_string();
+    }
+    chrono::Local::now().format(POST_DATE_FORMAT).to_string()
+}
+
+/// Driftwood only has tags, so categories become tags too
+fn merge_tags(categories: &[String], tags: &[String]) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    for tag in categories.iter().chain(tags.iter()) {
+        // tags are stored comma separated
+        let tag = tag.replace(',', " ").trim().to_string();
+        if tag.is_empty() || tag == DEFAULT_CATEGORY || merged.contains(&tag) {
+            continue;
+        }
+        merged.push(tag);
+    }
+    merged
+}
+
+/// Downloads images referenced by imported posts into the site's asset store
+struct ImageImporter {
+    store: AssetStore,
+    client: Option<reqwest::blocking::Client>,
+    // the same image is often used by several posts, only fetch it once
+    downloaded: HashMap<String, String>,
+}
+
+impl ImageImporter {
+    fn new(site: &SiteDetails, download_images: bool) -> Result<ImageImporter> {
+        let client = if download_images {
+            Some(
+                reqwest::blocking::Client::builder()
+                    .timeout(Duration::from_secs(30))
+                    .build()?,
+            )
+        } else {
+            None
+        };
+
+        Ok(ImageImporter {
+            store: AssetStore::for_site(site)?,
+            client,
+            downloaded: HashMap::new(),
+        })
+    }
+
+    /// Imports every <img> in the HTML and points it at the local copy,
+    /// images that fail keep their original URL
+    fn localize(&mut self, html: &str, post_title: &str, report: &mut ImportReport) -> String {
+        let img_src = Regex::new(r#"<img[^>]*?\ssrc=["']([^"']+)["']"#).unwrap();
+        let urls: Vec<String> = img_src
+            .captures_iter(html)
+            .map(|caps| caps[1].to_string())
+            .collect();
+
+        let mut html = html.to_string();
+        for url in urls {
+            if let Some(local_url) = self.import(&url, post_title, report) {
+                html = html.replace(&url, &local_url);
+            }
+        }
+        html
+    }
+
+    /// Downloads a single image, returns the local URL or flags it in the report
+    fn import(&mut self, url: &str, post_title: &str, report: &mut ImportReport) -> Option<String> {
+        if let Some(local_url) = self.downloaded.get(url) {
+            return Some(local_url.clone());
+        }
+
+        let result = match &self.client {
+            Some(client) => Self::download(client, &self.store, url),
+            None => Err(anyhow!("Image downloads were turned off for this import")),
+        };
+
+        match result {
+            Ok(file_name) => {
+                let local_url = AssetStore::url_for(&file_name);
+                self.downloaded.insert(url.to_string(), local_url.clone());
+                report.images_downloaded += 1;
+                Some(local_url)
+            }
+            Err(e) => {
+                println!("> Flagging image {}: {}", url, e);
+                report.flagged_images.push(FlaggedImage {
+                    post_title: post_title.to_string(),
+                    url: url.to_string(),
+                    reason: e.to_string(),
+                });
+                None
+            }
+        }
+    }
+
+    fn download(
+        client: &reqwest::blocking::Client,
+        store: &AssetStore,
+        url: &str,
+    ) -> Result<String> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(anyhow!("Not a downloadable URL"));
+        }
+
+        let response = client.get(url).send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Request failed: {}", response.status()));
+        }
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let extension = image_extension(url, &content_type)
+            .ok_or_else(|| anyhow!("Not an image ({})", content_type))?;
+
+        let bytes = response.bytes()?;
+        store.store_bytes(&bytes, extension)
+    }
+}
+
+/// Works out an image's extension from its URL, or its content type when the URL has none
+fn image_extension<'a>(url: &'a str, content_type: &str) -> Option<&'a str> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    if let Some((_, extension)) = path.rsplit_once('.') {
+        if IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+            return Some(extension);
+        }
+    }
+
+    match content_type.split(';').next().unwrap_or_default().trim() {
+        "image/png" => Some("png"),
+        "image/jpeg" => Some("jpg"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/svg+xml" => Some("svg"),
+        "image/avif" => Some("avif"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_WXR: &str = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<rss version="2.0"
+    xmlns:excerpt="http://wordpress.org/export/1.2/excerpt/"
+    xmlns:content="http://purl.org/rss/1.0/modules/content/"
+    xmlns:wp="http://wordpress.org/export/1.2/">
+<channel>
+    <title>Client Blog</title>
+    <item>
+        <title>What&apos;s new in v2.0?</title>
+        <pubDate>Wed, 01 May 2019 13:45:00 +0000</pubDate>
+        <content:encoded><![CDATA[First paragraph.
+
+<img src="https://example.com/wp-content/uploads/photo.jpg" alt="" />
+
+Second <strong>paragraph</strong>.]]></content:encoded>
+        <excerpt:encoded><![CDATA[A short excerpt]]></excerpt:encoded>
+        <wp:post_id>12</wp:post_id>
+        <wp:post_name><![CDATA[whats-new-in-v2-0]]></wp:post_name>
+        <wp:post_date><![CDATA[2019-05-01 13:45:00]]></wp:post_date>
+        <wp:status><![CDATA[publish]]></wp:status>
+        <wp:post_type><![CDATA[post]]></wp:post_type>
+        <category domain="category" nicename="uncategorized"><![CDATA[Uncategorized]]></category>
+        <category domain="category" nicename="news"><![CDATA[News]]></category>
+        <category domain="post_tag" nicename="release"><![CDATA[Release]]></category>
+        <wp:postmeta>
+            <wp:meta_key><![CDATA[_thumbnail_id]]></wp:meta_key>
+            <wp:meta_value><![CDATA[13]]></wp:meta_value>
+        </wp:postmeta>
+    </item>
+    <item>
+        <title>header</title>
+        <wp:post_id>13</wp:post_id>
+        <wp:post_type><![CDATA[attachment]]></wp:post_type>
+        <wp:attachment_url><![CDATA[https://example.com/wp-content/uploads/header.png]]></wp:attachment_url>
+    </item>
+</channel>
+</rss>"#;
+
+    #[test]
+    fn test_parse_wxr() -> Result<()> {
+        let items = parse_wxr(SAMPLE_WXR)?;
+        assert_eq!(items.len(), 2);
+
+        let post = &items[0];
+        assert_eq!(post.title, "What's new in v2.0?");
+        assert_eq!(post.post_name, "whats-new-in-v2-0");
+        assert_eq!(post.post_type, "post");
+        assert_eq!(post.status, "publish");
+        assert_eq!(post.excerpt, "A short excerpt");
+        assert_eq!(post.categories, vec!["Uncategorized", "News"]);
+        assert_eq!(post.tags, vec!["Release"]);
+        assert_eq!(post.thumbnail_id, "13");
+        assert_eq!(
+            items[1].attachment_url,
+            "https://example.com/wp-content/uploads/header.png"
+        );
+
+        assert_eq!(
+            merge_tags(&post.categories, &post.tags),
+            vec!["News", "Release"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_content_and_dates() {
+        let html = wpautop("First paragraph.\n\nSecond <strong>paragraph</strong>.");
+        assert_eq!(
+            html,
+            "<p>First paragraph.</p>\n<p>Second <strong>paragraph</strong>.</p>"
+        );
+        assert_eq!(
+            html_to_markdown(&html),
+            "First paragraph.\n\nSecond **paragraph**."
+        );
+        assert_eq!(
+            wpautop("Some code:\n\n<pre>let a = 1;</pre>\n\nAfter it."),
+            "<p>Some code:</p>\n<pre>let a = 1;</pre>\n<p>After it.</p>"
+        );
+        // blank lines inside blocks don't split them, code keeps its empty lines
+        assert_eq!(
+            wpautop("Code:\n\n<pre>fn main() {\n\n    run();\n}</pre>\n<blockquote><div>One.\n\nTwo.</div></blockquote>\n\nDone."),
+            "<p>Code:</p>\n<pre>fn main() {\n\n    run();\n}</pre>\n<blockquote><div>One.\n\nTwo.</div></blockquote>\n<p>Done.</p>"
+        );
+        assert_eq!(
+            wpautop("<h2>Title</h2>\n\nText\nmore"),
+            "<h2>Title</h2>\n<p>Text<br />\nmore</p>"
+        );
+        assert_eq!(wpautop("<p>Already wrapped</p>"), "<p>Already wrapped</p>");
+
+        assert_eq!(convert_date("2019-05-01 13:45:00", ""), "2019/05/01 01:45 PM");
+        assert_eq!(
+            convert_date("0000-00-00 00:00:00", "Wed, 01 May 2019 13:45:00 +0000"),
+            "2019/05/01 01:45 PM"
+        );
+
+        assert_eq!(
+            image_extension("https://example.com/a/photo.JPG?w=300", ""),
+            Some("JPG")
+        );
+        assert_eq!(image_extension("https://example.com/image", "image/png"), Some("png"));
+        assert_eq!(image_extension("https://example.com/page", "text/html"), None);
+    }
+}